@@ -16,6 +16,24 @@ macro_rules! json {
     };
 }
 
+macro_rules! png {
+    ($path:literal) => {
+        static_content!($path, "image/png")
+    };
+}
+
+macro_rules! svg {
+    ($path:literal) => {
+        static_content!($path, "image/svg+xml")
+    };
+}
+
+macro_rules! ico {
+    ($path:literal) => {
+        static_content!($path, "image/x-icon")
+    };
+}
+
 macro_rules! static_content {
     ($path:literal, $mime:literal) => {{
         const DATA: &[u8] = ::std::include_bytes!(::std::concat!(
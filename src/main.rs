@@ -5,22 +5,22 @@ use axum::extract::{FromRequestParts, Path, Query, State};
 use axum::http::request::Parts;
 use axum::http::{header, StatusCode};
 use axum::response::sse::{Event, KeepAlive, Sse};
-use axum::response::{Html, IntoResponse};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::{Form, Router};
+use axum::{Form, Json, Router};
 use clap::Parser;
 use cookie::Cookie;
 use futures_util::stream::Stream;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio_stream::StreamExt as _;
 use tower_http::compression::CompressionLayer;
 
 use std::borrow::Borrow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::Infallible;
 use std::ops::Deref;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
 // import macros first before other modules
 #[macro_use]
@@ -28,6 +28,7 @@ mod macros;
 
 mod config;
 mod middleware;
+mod notifications;
 mod session;
 mod template_helpers;
 mod transmission;
@@ -39,17 +40,91 @@ mod unix_sock;
 async fn main() -> anyhow::Result<()> {
     let args = config::Args::parse();
 
-    let config = std::fs::read_to_string(&args.config).context(format!(
-        r#"Failed to read configuration file "{}""#,
-        args.config.display()
-    ))?;
+    let config = match &args.config {
+        Some(path) => {
+            let config = std::fs::read_to_string(path).context(format!(
+                r#"Failed to read configuration file "{}""#,
+                path.display()
+            ))?;
 
-    // don't provide error context here since the toml error will be self explanatory
-    let config: config::Config = toml::from_str(&config)?;
+            // don't provide error context here since the toml error will be self explanatory
+            toml::from_str(&config)?
+        }
+        None => {
+            // ignore any variables that aren't unicode rather than panicking
+            let vars = std::env::vars_os().filter_map(|(name, value)| {
+                Some((name.into_string().ok()?, value.into_string().ok()?))
+            });
+            config::Config::from_env_vars(vars)?
+        }
+    };
+
+    if args.verbose {
+        println!("Using configuration: {config:#?}");
+    } else {
+        println!(
+            "Listening on {}, using Transmission at {}",
+            config.connection.bind_address,
+            config.connection.rpc_url.redacted(),
+        );
+    }
 
     let bind_addr = config.connection.bind_address.clone();
     let bind_unix_perms = config.connection.bind_unix_perms;
-    let shared_state = Arc::new(AppState::new(config));
+    let bind_unix_idle_timeout = Duration::from_secs(config.connection.bind_unix_idle_timeout_secs);
+    let shared_state = Arc::new(AppState::new(config)?);
+    #[cfg(target_os = "linux")]
+    let unix_connections = Arc::clone(&shared_state.unix_connections);
+
+    // periodically remove sessions that expired but were never accessed again
+    tokio::spawn({
+        let shared_state = Arc::clone(&shared_state);
+        async move {
+            const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+            loop {
+                tokio::time::sleep(SWEEP_INTERVAL).await;
+                shared_state.sessions.remove_expired_sessions();
+            }
+        }
+    });
+
+    if let Some(timeout) = shared_state.config.connection.startup_probe_timeout_secs {
+        let timeout = Duration::from_secs(timeout);
+        let rpc_url = &shared_state.config.connection.rpc_url;
+        let client = &shared_state.http_client;
+        let url = rpc_url.redacted();
+
+        if transmission::rpc::wait_until_reachable(client, rpc_url, timeout).await {
+            println!("Transmission is reachable at {url}");
+        } else {
+            // transmission may still start later, so don't exit
+            println!("Transmission isn't reachable at {url} after {timeout:?}, starting anyway");
+        }
+    }
+
+    if shared_state.config.connection.startup_check {
+        let rpc_url = &shared_state.config.connection.rpc_url;
+        let diagnosis =
+            transmission::rpc::Diagnosis::check(&shared_state.http_client, rpc_url).await;
+        shared_state.record_diagnosis(&diagnosis);
+        println!(
+            "Checked Transmission at {}: {}",
+            rpc_url.redacted(),
+            diagnosis.guidance(),
+        );
+    }
+
+    // the api responses have per-session data, so shouldn't be stored by browsers or proxies
+    #[rustfmt::skip]
+    let api = Router::new()
+        .route("/api/version", get(api_version_get))
+        .route("/api/token", post(api_token_post))
+        .route("/api/session", get(api_session_get))
+        .route("/api/counts", get(api_counts_get))
+        .route("/api/torrents", get(api_torrents_get))
+        .route("/api/magnets", get(api_magnets_get))
+        .route("/api/health", get(api_health_get))
+        .route_layer(axum::middleware::from_fn(middleware::no_store));
 
     #[rustfmt::skip]
     let app = Router::new()
@@ -60,19 +135,65 @@ async fn main() -> anyhow::Result<()> {
         .route("/start-torrent", post(start_torrent_post))
         .route("/pause-torrent", post(pause_torrent_post))
         .route("/verify-torrent", post(verify_torrent_post))
+        .route("/verify-errored", post(verify_errored_post))
+        .route("/bulk-action", post(bulk_action_post))
+        .route("/bulk-move", post(bulk_move_post))
+        .route("/bulk-magnets", post(bulk_magnets_post))
         .route("/add-torrent", get(add_torrent_get))
         .route("/add-torrent", post(add_torrent_post))
         .route("/torrent/:hash", get(torrent_get))
+        .route("/torrent/:hash/edit", get(edit_torrent_get))
+        .route("/torrent/:hash/edit", post(edit_torrent_post))
+        .route("/torrent/:hash/files", post(torrent_files_post))
+        .route("/torrent/:hash/magnet", get(torrent_magnet_get))
+        .route("/torrent/:hash/seed-idle", post(torrent_seed_idle_post))
+        .route("/torrent/:hash/priority", post(torrent_priority_post))
+        .route("/torrent/:hash/queue", post(torrent_queue_post))
+        .route("/torrent/:hash/favorite", post(torrent_favorite_post))
+        .route("/export/magnets", get(export_magnets_get))
+        .route("/settings", get(settings_get))
+        .route("/settings/blocklist", post(blocklist_post))
+        .route("/settings/blocklist/update", post(blocklist_update_post))
+        .route("/settings/port-test", post(port_test_post))
+        .route("/settings/network", get(network_settings_get))
+        .route("/settings/network", post(network_settings_post))
+        .route("/settings/groups", get(groups_get))
+        .route("/settings/groups", post(groups_post))
+        .route("/settings/sessions", get(sessions_get))
+        .route("/settings/sessions/revoke", post(sessions_revoke_post))
+        .route("/shutdown-transmission", post(shutdown_transmission_post))
         .route("/stub/torrent", get(stub_torrent_get))
         .route("/stub/torrents", get(stub_torrents_get))
+        .route("/stub/torrent-status", get(stub_torrent_status_get))
         .route("/sse/torrent", get(sse_torrent_get))
         .route("/sse/torrents", get(sse_torrents_get))
+        .merge(api)
         .route("/static/app/manifest.json", json!("static/app/manifest.json"))
+        .route("/static/app/icon.svg", svg!("static/app/icon.svg"))
+        .route("/static/app/icon-192.png", png!("static/app/icon-192.png"))
+        .route("/static/app/icon-512.png", png!("static/app/icon-512.png"))
+        // browsers request these paths even if the page doesn't link to them
+        .route("/favicon.ico", ico!("static/app/favicon.ico"))
+        .route("/apple-touch-icon.png", png!("static/app/apple-touch-icon.png"))
         .route("/static/css/base.css", css!("static/css/base.css"))
         .route("/static/css/index.css", css!("static/css/index.css"))
         .route("/static/js/htmx.js", js!("static/js/htmx.js"))
         .route("/static/js/sse.js", js!("static/js/sse.js"))
+        .route("/static/js/copy.js", js!("static/js/copy.js"))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.config.security.debug_headers,
+            middleware::rpc_duration_header,
+        ))
         .layer(axum::middleware::from_fn(middleware::unauthorized_redirect))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.config.connection.display_name.clone(),
+            middleware::unavailable_page,
+        ))
+        .layer(axum::middleware::from_fn(middleware::redact_session_cookie))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.config.performance.sse_disable_proxy_buffering,
+            middleware::sse_headers,
+        ))
         .layer(axum::middleware::from_fn(middleware::compress_sse))
         .layer(CompressionLayer::new())
         .with_state(shared_state);
@@ -91,7 +212,14 @@ async fn main() -> anyhow::Result<()> {
             let bind_addr = bind_addr.path();
 
             #[cfg(target_os = "linux")]
-            unix_sock::serve(bind_addr, bind_unix_perms, app).await?;
+            unix_sock::serve(
+                bind_addr,
+                bind_unix_perms,
+                unix_connections,
+                bind_unix_idle_timeout,
+                app,
+            )
+            .await?;
 
             // bsd and windows have support for path-based unix sockets, but they work a bit
             // differently so they would need more testing and changes to support
@@ -103,6 +231,13 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// How long a request waits for other requests to transmission to finish before giving up, if
+/// `max_concurrent_rpc` requests are already in-flight.
+const RPC_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a connection to transmission before assuming that it's unavailable.
+const RPC_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 struct AppState {
     config: config::Config,
@@ -110,15 +245,143 @@ struct AppState {
     // reqwest says that a `Client` is a pool of connections and we should reuse it, so we'll use it
     // for all rpc connections across all sessions
     http_client: reqwest::Client,
+    /// Limits the requests made to transmission by all sessions.
+    rpc_limiter: Arc<transmission::rpc::RpcLimiter>,
+    /// How credentials are sent to transmission.
+    rpc_auth: transmission::rpc::AuthStrategy,
+    notifier: notifications::Notifier,
+    /// Whether transmission requires credentials, once known. See [`AppState::rpc_auth_required`].
+    rpc_auth_required: OnceLock<bool>,
+    /// Limits the connections to the unix socket, if the server is bound to one.
+    #[cfg(target_os = "linux")]
+    unix_connections: Arc<unix_sock::ConnectionLimiter>,
 }
 
 impl AppState {
-    pub fn new(config: config::Config) -> Self {
-        Self {
+    pub fn new(config: config::Config) -> anyhow::Result<Self> {
+        // admins are identified by the username given at login, which transmission only checks
+        // with basic auth
+        if !config.security.admin_users.is_empty()
+            && config.connection.rpc_auth != config::RpcAuthMethod::Basic
+        {
+            anyhow::bail!(r#"The "admin_users" option requires "rpc_auth" to be "basic""#);
+        }
+
+        Ok(Self {
+            notifier: notifications::Notifier::new(&config.notifications),
+            http_client: Self::http_client(&config.connection)?,
+            rpc_auth: transmission::rpc::AuthStrategy::from_config(&config.connection)?,
+            rpc_limiter: Arc::new(transmission::rpc::RpcLimiter::new(
+                config.performance.max_concurrent_rpc,
+                RPC_QUEUE_TIMEOUT,
+            )),
+            #[cfg(target_os = "linux")]
+            unix_connections: Arc::new(unix_sock::ConnectionLimiter::new(
+                config.connection.bind_unix_max_connections,
+            )),
             config,
             sessions: Default::default(),
-            http_client: Default::default(),
+            rpc_auth_required: OnceLock::new(),
+        })
+    }
+
+    /// Whether transmission requires credentials, or `None` if it couldn't be determined. This only
+    /// changes if transmission is reconfigured, so it's cached after the first successful check.
+    async fn rpc_auth_required(&self) -> Option<bool> {
+        if let Some(x) = self.rpc_auth_required.get() {
+            return Some(*x);
+        }
+
+        let url = &self.config.connection.rpc_url;
+        let diagnosis = transmission::rpc::Diagnosis::check(&self.http_client, url).await;
+        self.record_diagnosis(&diagnosis);
+        self.rpc_auth_required.get().copied()
+    }
+
+    /// Cache whether transmission requires credentials if the diagnosis says so, so that other
+    /// checks of transmission (like the startup check) save a request later.
+    fn record_diagnosis(&self, diagnosis: &transmission::rpc::Diagnosis) {
+        let required = match diagnosis {
+            transmission::rpc::Diagnosis::AuthRequired => true,
+            transmission::rpc::Diagnosis::Reachable => false,
+            _ => return,
+        };
+        let _ = self.rpc_auth_required.set(required);
+    }
+
+    fn http_client(config: &config::ConfigConnection) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+
+        #[cfg(feature = "tls")]
+        {
+            if let Some(path) = &config.rpc_ca_cert {
+                let pem = std::fs::read(path).context(format!(
+                    r#"Failed to read the CA certificate "{}""#,
+                    path.display()
+                ))?;
+                let cert = reqwest::Certificate::from_pem(&pem).context(format!(
+                    r#"Failed to parse the CA certificate "{}""#,
+                    path.display()
+                ))?;
+                builder = builder.add_root_certificate(cert);
+            }
+
+            if let Some(cert_path) = &config.rpc_client_cert {
+                let read = |path: &std::path::Path| {
+                    std::fs::read(path).context(format!(
+                        r#"Failed to read the client certificate file "{}""#,
+                        path.display()
+                    ))
+                };
+
+                let cert = read(cert_path)?;
+                let identity = match &config.rpc_client_key {
+                    Some(key_path) => reqwest::Identity::from_pkcs8_pem(&cert, &read(key_path)?),
+                    None => {
+                        let password = config.rpc_client_cert_password.as_deref();
+                        reqwest::Identity::from_pkcs12_der(&cert, password.unwrap_or(""))
+                    }
+                };
+                let identity = identity.context(format!(
+                    r#"Failed to load the client certificate "{}""#,
+                    cert_path.display()
+                ))?;
+
+                builder = builder.identity(identity);
+            } else if config.rpc_client_key.is_some() {
+                anyhow::bail!(r#"The "rpc_client_key" option requires "rpc_client_cert""#);
+            }
+
+            if config.rpc_danger_accept_invalid_certs {
+                println!(
+                    "WARNING: Transmission's TLS certificate will not be verified. This allows anyone on the network path to Transmission to read and modify requests, including the login credentials."
+                );
+                builder = builder.danger_accept_invalid_certs(true);
+            }
+        }
+
+        #[cfg(not(feature = "tls"))]
+        if config.rpc_ca_cert.is_some()
+            || config.rpc_danger_accept_invalid_certs
+            || config.rpc_client_cert.is_some()
+            || config.rpc_client_key.is_some()
+            || config.rpc_client_cert_password.is_some()
+        {
+            anyhow::bail!(
+                r#"The "rpc_ca_cert", "rpc_danger_accept_invalid_certs", and "rpc_client_*" options require transportal to be built with the "tls" feature"#
+            );
+        }
+
+        // a separate connect timeout lets us tell whether transmission is down or only slow
+        let mut connect_timeout = RPC_CONNECT_TIMEOUT;
+        if let Some(timeout) = config.rpc_timeout_secs {
+            let timeout = Duration::from_secs(timeout);
+            builder = builder.timeout(timeout);
+            connect_timeout = connect_timeout.min(timeout);
         }
+        builder = builder.connect_timeout(connect_timeout);
+
+        builder.build().context("Failed to build the HTTP client")
     }
 }
 
@@ -133,71 +396,587 @@ struct TorrentQuery {
     hash: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 struct TorrentListQuery {
     #[serde(rename = "q")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<TorrentSortField>,
     #[serde(rename = "dir")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     sort_direction: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "TorrentListShow::is_all")]
+    show: TorrentListShow,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "TorrentListStatus::is_all")]
+    status: TorrentListStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    view: Option<TorrentListView>,
+}
+
+impl TorrentListQuery {
+    /// The name filter, if there is a non-empty one.
+    fn name_filter(&self) -> Option<&str> {
+        self.filter.as_deref().filter(|x| !x.is_empty())
+    }
+
+    fn sort_field(&self) -> TorrentSortField {
+        self.sort.unwrap_or_default()
+    }
+
+    /// Whether the torrents should be sorted in ascending order. If the query doesn't specify a
+    /// direction, the sort field's default direction is used.
+    fn ascending(&self) -> bool {
+        match self.sort_direction.as_deref() {
+            Some("ascend") => true,
+            Some("descend") => false,
+            _ => self.sort_field().ascending_by_default(),
+        }
+    }
+
+    fn is_sorted_by(&self, field: &TorrentSortField) -> bool {
+        self.sort_field() == *field
+    }
+
+    fn has_status(&self, status: &TorrentListStatus) -> bool {
+        self.status == *status
+    }
+
+    /// An arrow showing the sort direction if the torrents are sorted by `field`.
+    fn sort_indicator(&self, field: &TorrentSortField) -> &'static str {
+        match (self.is_sorted_by(field), self.ascending()) {
+            (false, _) => "",
+            (true, true) => "▲",
+            (true, false) => "▼",
+        }
+    }
+
+    /// The index URL that sorts the torrents by `field`. If the torrents are already sorted by
+    /// `field`, the direction is toggled. Other query parameters are kept.
+    fn sort_url(&self, field: &TorrentSortField) -> String {
+        let ascending = if self.is_sorted_by(field) {
+            !self.ascending()
+        } else {
+            field.ascending_by_default()
+        };
+
+        let query = Self {
+            sort: Some(*field),
+            sort_direction: Some(if ascending { "ascend" } else { "descend" }.to_string()),
+            ..self.clone()
+        };
+
+        match serde_urlencoded::to_string(&query) {
+            Ok(query) if !query.is_empty() => format!("/?{query}"),
+            _ => "/".to_string(),
+        }
+    }
+
+    /// If the query doesn't specify a view, use the view stored in the cookie.
+    fn with_view_from_cookie(mut self, headers: &header::HeaderMap) -> Self {
+        if self.view.is_none() {
+            self.view = cookie_from_headers(headers, VIEW_COOKIE).and_then(|x| x.parse().ok());
+        }
+        self
+    }
+}
+
+/// Which torrents are shown in the torrent list.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum TorrentListShow {
+    #[default]
+    All,
+    /// Torrents that have errors, are stalled, or have no peers.
+    Attention,
+}
+
+impl TorrentListShow {
+    fn is_all(&self) -> bool {
+        *self == Self::All
+    }
+}
+
+/// Which torrents are shown in the torrent list, based on their status. Each option covers several
+/// of transmission's statuses.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum TorrentListStatus {
+    #[default]
+    All,
+    /// Torrents that are downloading or queued to download.
+    Downloading,
+    /// Torrents that are seeding or queued to seed.
+    Seeding,
+}
+
+impl TorrentListStatus {
+    /// The options other than `All`, which are shown as toggles.
+    const CHIPS: [Self; 2] = [Self::Downloading, Self::Seeding];
+
+    fn is_all(&self) -> bool {
+        *self == Self::All
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Downloading => "downloading",
+            Self::Seeding => "seeding",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::All => "All",
+            Self::Downloading => "Downloading",
+            Self::Seeding => "Seeding",
+        }
+    }
+
+    /// The transmission statuses included, or `None` if all torrents are included.
+    fn statuses(&self) -> Option<&'static [transmission::types::TorrentStatus]> {
+        use transmission::types::TorrentStatus;
+
+        match self {
+            Self::All => None,
+            Self::Downloading => Some(&[TorrentStatus::DownloadQueued, TorrentStatus::Downloading]),
+            Self::Seeding => Some(&[TorrentStatus::SeedQueued, TorrentStatus::Seeding]),
+        }
+    }
+}
+
+/// The field that the torrent list is sorted by.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum TorrentSortField {
+    #[default]
+    DateAdded,
+    Name,
+    Status,
+    Progress,
+    Size,
+    Eta,
+}
+
+impl TorrentSortField {
+    const ALL: [Self; 6] = [
+        Self::DateAdded,
+        Self::Name,
+        Self::Status,
+        Self::Progress,
+        Self::Size,
+        Self::Eta,
+    ];
+
+    /// The fields shown as columns in the table view, in order.
+    const COLUMNS: [Self; 5] = [
+        Self::Name,
+        Self::Status,
+        Self::Progress,
+        Self::Size,
+        Self::Eta,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::DateAdded => "date-added",
+            Self::Name => "name",
+            Self::Status => "status",
+            Self::Progress => "progress",
+            Self::Size => "size",
+            Self::Eta => "eta",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::DateAdded => "Date added",
+            Self::Name => "Name",
+            Self::Status => "Status",
+            Self::Progress => "Progress",
+            Self::Size => "Size",
+            Self::Eta => "ETA",
+        }
+    }
+
+    /// Newest, most complete, and largest torrents are shown first by default.
+    fn ascending_by_default(&self) -> bool {
+        match self {
+            Self::Name | Self::Status | Self::Eta => true,
+            Self::DateAdded | Self::Progress | Self::Size => false,
+        }
+    }
+
+    /// The value of this field for the torrent, or `None` if it's missing or unknown.
+    fn key(&self, torrent: &transmission::types::Torrent) -> Option<SortKey> {
+        let key = match self {
+            Self::DateAdded => SortKey::Number(torrent.added_date()? as f64),
+            Self::Name => SortKey::Text(torrent.name()?.to_lowercase()),
+            Self::Status => SortKey::Number(torrent.status()? as u8 as f64),
+            Self::Progress => SortKey::Number(torrent.percent_done()?),
+            Self::Size => SortKey::Number(torrent.size_when_done()? as f64),
+            // a negative eta means that it's unknown or not applicable
+            Self::Eta => SortKey::Number(torrent.eta().filter(|x| *x >= 0)? as f64),
+        };
+
+        Some(key)
+    }
+}
+
+/// A torrent's value for the field it's being sorted by.
+#[derive(Clone, Debug)]
+enum SortKey {
+    Number(f64),
+    Text(String),
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Number(_), Self::Text(_)) => std::cmp::Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => std::cmp::Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for SortKey {}
+
+/// The name of the cookie that remembers the last view chosen by the user.
+const VIEW_COOKIE: &str = "view";
+
+/// How the torrents in the torrent list are displayed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum TorrentListView {
+    #[default]
+    Cards,
+    /// A compact table with one row per torrent.
+    Table,
+}
+
+impl TorrentListView {
+    /// The torrent fields that are always requested for the torrent list, even if the fields are
+    /// configured with `performance.list_fields`. These are needed to identify, filter, and sort
+    /// the torrents, and for notifications.
+    const REQUIRED_KEYS: &'static [transmission::types::TorrentGetKey] = &[
+        transmission::types::TorrentGetKey::HashString,
+        transmission::types::TorrentGetKey::Name,
+        transmission::types::TorrentGetKey::Status,
+        transmission::types::TorrentGetKey::PercentDone,
+        transmission::types::TorrentGetKey::SizeWhenDone,
+        transmission::types::TorrentGetKey::AddedDate,
+    ];
+
+    /// The torrent fields to request for the torrent list with this view. If `list_fields` is
+    /// given, it replaces the view's default fields (other than `REQUIRED_KEYS`).
+    fn keys(
+        &self,
+        list_fields: Option<&[transmission::types::TorrentGetKey]>,
+    ) -> Vec<transmission::types::TorrentGetKey> {
+        let Some(list_fields) = list_fields else {
+            return self.default_keys();
+        };
+
+        let mut keys = Self::REQUIRED_KEYS.to_vec();
+        for key in list_fields {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+
+        keys
+    }
+
+    /// The torrent fields needed to filter, sort, and render the torrent list with this view.
+    fn default_keys(&self) -> Vec<transmission::types::TorrentGetKey> {
+        use transmission::types::TorrentGetKey;
+
+        let mut keys = vec![
+            TorrentGetKey::HashString,
+            TorrentGetKey::Name,
+            TorrentGetKey::Status,
+            TorrentGetKey::PercentDone,
+            TorrentGetKey::SizeWhenDone,
+            TorrentGetKey::Eta,
+            TorrentGetKey::ErrorString,
+            // sorting
+            TorrentGetKey::AddedDate,
+            // attention badges and filter
+            TorrentGetKey::Error,
+            TorrentGetKey::IsStalled,
+            TorrentGetKey::PeersConnected,
+        ];
+
+        match self {
+            Self::Cards => keys.extend([
+                TorrentGetKey::LeftUntilDone,
+                TorrentGetKey::IsFinished,
+                TorrentGetKey::Wanted,
+                TorrentGetKey::Labels,
+            ]),
+            Self::Table => keys.extend([
+                TorrentGetKey::BandwidthPriority,
+                TorrentGetKey::QueuePosition,
+            ]),
+        }
+
+        keys
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cards => "cards",
+            Self::Table => "table",
+        }
+    }
+}
+
+impl std::str::FromStr for TorrentListView {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cards" => Ok(Self::Cards),
+            "table" => Ok(Self::Table),
+            _ => Err(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct AddTorrentQuery {
     magnet: String,
     paused: Option<String>,
+    download_dir: Option<String>,
+    /// Cookies sent when transmission downloads a torrent file from a URL, in the format of a
+    /// `Cookie` header.
+    cookies: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "partials/torrent.html")]
 struct TorrentPartialTemplate {
-    details: BTreeMap<transmission::types::TorrentGetKey, serde_json::Value>,
+    details: transmission::types::Torrent,
+}
+
+impl TorrentPartialTemplate {
+    /// The torrent fields shown in the torrent details, except for optional fields that depend on
+    /// the configuration.
+    const KEYS: &'static [transmission::types::TorrentGetKey] = &[
+        transmission::types::TorrentGetKey::DateCreated,
+        transmission::types::TorrentGetKey::AddedDate,
+        transmission::types::TorrentGetKey::Id,
+        transmission::types::TorrentGetKey::Name,
+        transmission::types::TorrentGetKey::HashString,
+        transmission::types::TorrentGetKey::PercentComplete,
+        transmission::types::TorrentGetKey::PercentDone,
+        transmission::types::TorrentGetKey::Status,
+        transmission::types::TorrentGetKey::IsStalled,
+        transmission::types::TorrentGetKey::Error,
+        transmission::types::TorrentGetKey::ErrorString,
+        transmission::types::TorrentGetKey::Availability,
+        transmission::types::TorrentGetKey::DesiredAvailable,
+        transmission::types::TorrentGetKey::LeftUntilDone,
+        transmission::types::TorrentGetKey::SizeWhenDone,
+        transmission::types::TorrentGetKey::FileCount,
+        transmission::types::TorrentGetKey::Wanted,
+        transmission::types::TorrentGetKey::SeedIdleLimit,
+        transmission::types::TorrentGetKey::SeedIdleMode,
+        transmission::types::TorrentGetKey::EtaIdle,
+        transmission::types::TorrentGetKey::IsPrivate,
+    ];
+}
+
+/// Only the torrent's status and badges, which is also included in the torrent details.
+#[derive(Template)]
+#[template(path = "partials/torrent-status.html")]
+struct TorrentStatusPartialTemplate {
+    details: transmission::types::Torrent,
+}
+
+impl TorrentStatusPartialTemplate {
+    const KEYS: &'static [transmission::types::TorrentGetKey] = &[
+        transmission::types::TorrentGetKey::Status,
+        transmission::types::TorrentGetKey::IsStalled,
+        transmission::types::TorrentGetKey::Error,
+        transmission::types::TorrentGetKey::ErrorString,
+    ];
 }
 
 #[derive(Template)]
 #[template(path = "stubs/torrent.html")]
 struct TorrentStubTemplate {
     hash: String,
+    /// Prepended to the SSE event names.
+    event_prefix: String,
     partial: TorrentPartialTemplate,
 }
 
 #[derive(Template)]
 #[template(path = "partials/torrent-list.html")]
 struct TorrentListPartialTemplate {
-    torrents: Vec<BTreeMap<transmission::types::TorrentGetKey, serde_json::Value>>,
+    torrents: Vec<transmission::types::Torrent>,
+    /// The query that the torrents were listed with, used for the table's sort links.
+    query: TorrentListQuery,
+    view: TorrentListView,
+    units: transmission::types::Units,
+    /// The number of torrents that completed since the user last viewed the index, which the page
+    /// shows in its title.
+    unread_completions: usize,
+    /// Hashes of the torrents that the user pinned to the top of the list.
+    favorites: HashSet<String>,
+}
+
+impl TorrentListPartialTemplate {
+    /// Move the session's favorite torrents to the top of the list, regardless of the sort. The
+    /// order is otherwise unchanged.
+    fn pin_favorites(&mut self, favorites: HashSet<String>) {
+        // the sort is stable, so the torrents stay sorted within the favorites and the others
+        self.torrents
+            .sort_by_key(|x| !x.hash().is_some_and(|hash| favorites.contains(hash)));
+        self.favorites = favorites;
+    }
+
+    fn is_favorite(&self, torrent: &transmission::types::Torrent) -> bool {
+        torrent
+            .hash()
+            .is_some_and(|hash| self.favorites.contains(hash))
+    }
+
+    /// Render each torrent as a separate out-of-band swap. Returns the hash and html of each
+    /// torrent, in the same order as the torrent list. Torrents in `reuse` (keyed by hash) aren't
+    /// rendered again, and use the given html instead.
+    fn render_rows(&self, reuse: &HashMap<&str, &str>) -> askama::Result<Vec<(String, String)>> {
+        self.torrents
+            .iter()
+            .map(|torrent| {
+                let hash = torrent
+                    .get(&transmission::types::TorrentGetKey::HashString)
+                    .and_then(|x| x.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                if let Some(html) = reuse.get(hash.as_str()) {
+                    return Ok((hash, html.to_string()));
+                }
+                let html = TorrentListItemPartialTemplate {
+                    torrent,
+                    favorite: self.is_favorite(torrent),
+                    oob: true,
+                    view: self.view,
+                    units: &self.units,
+                }
+                .render()?;
+                Ok((hash, html))
+            })
+            .collect()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "partials/torrent-list-item.html")]
+struct TorrentListItemPartialTemplate<'a> {
+    torrent: &'a transmission::types::Torrent,
+    /// Whether the user pinned the torrent to the top of the list.
+    favorite: bool,
+    /// Whether the torrent should be rendered as an htmx out-of-band swap.
+    oob: bool,
+    view: TorrentListView,
+    units: &'a transmission::types::Units,
+}
+
+#[derive(Template)]
+#[template(path = "partials/torrent-count.html")]
+struct TorrentCountPartialTemplate {
+    count: usize,
+    unread_completions: usize,
+    /// Whether the count should be rendered as an htmx out-of-band swap.
+    oob: bool,
 }
 
 #[derive(Template)]
 #[template(path = "stubs/torrent-list.html")]
 struct TorrentListStubTemplate {
-    filter: Option<String>,
+    query: TorrentListQuery,
+    /// Prepended to the SSE event names.
+    event_prefix: String,
     partial: TorrentListPartialTemplate,
 }
 
+impl TorrentListStubTemplate {
+    /// The URL of the SSE stream, which must list the torrents using the same query.
+    fn sse_url(&self) -> String {
+        match serde_urlencoded::to_string(&self.query) {
+            Ok(query) if !query.is_empty() => format!("/sse/torrents?{query}"),
+            _ => "/sse/torrents".to_string(),
+        }
+    }
+}
+
 async fn index_get(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Query(TorrentListQuery {
-        filter,
-        sort_direction,
-    }): Query<TorrentListQuery>,
+    headers: header::HeaderMap,
+    Query(query): Query<TorrentListQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let filter_str = filter.as_deref();
-    let torrents = torrent_list(session.data(), &state.http_client, filter_str).await?;
+    // remember the view if the user chose one
+    let view_cookie = query.view.map(|view| {
+        let secure = if state.config.security.secure_cookie_attribute {
+            "Secure; "
+        } else {
+            ""
+        };
+        let view = view.as_str();
+        format!("{VIEW_COOKIE}={view}; {secure}HttpOnly; SameSite=Lax; Path=/; Max-Age=31536000")
+    });
+
+    let query = query.with_view_from_cookie(&headers);
+    let mut torrents = torrent_list(&state, session.data(), &query).await?;
+    torrents.pin_favorites(session.favorites());
+
+    // the user is now seeing any completed torrents
+    session.clear_unread_completions();
 
     #[derive(Template)]
     #[template(path = "index.html")]
     struct IndexTemplate {
+        display_name: String,
         ascending: bool,
+        flash: Option<String>,
         stub: TorrentListStubTemplate,
     }
 
-    Ok(IndexTemplate {
-        ascending: sort_direction.map(|x| x == "ascend").unwrap_or(false),
+    let template = IndexTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        ascending: query.ascending(),
+        flash: session.take_flash(),
         stub: TorrentListStubTemplate {
-            filter,
+            query,
+            event_prefix: state.config.performance.sse_event_prefix.clone(),
             partial: torrents,
         },
-    })
+    };
+
+    Ok((
+        view_cookie.map(|cookie| [(header::SET_COOKIE, cookie)]),
+        template,
+    ))
 }
 
 async fn torrent_get(
@@ -205,7 +984,9 @@ async fn torrent_get(
     SessionArc(session): SessionArc,
     Path(hash): Path<String>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let torrent = torrent_details(session.data(), &state.http_client, &hash).await?;
+    let show_download_dir = state.config.security.show_download_dir;
+    let torrent =
+        torrent_details(session.data(), &state.http_client, &hash, show_download_dir).await?;
 
     let Some(torrent) = torrent else {
         return Err(StatusCode::NOT_FOUND);
@@ -214,413 +995,2844 @@ async fn torrent_get(
     #[derive(Template)]
     #[template(path = "torrent.html")]
     struct TorrentTemplate {
+        display_name: String,
         stub: TorrentStubTemplate,
     }
 
     Ok(TorrentTemplate {
+        display_name: state.config.connection.display_name.clone(),
         stub: TorrentStubTemplate {
             hash,
+            event_prefix: state.config.performance.sse_event_prefix.clone(),
             partial: torrent,
         },
     })
 }
 
-async fn login_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    #[derive(Template)]
-    #[template(path = "login.html")]
-    struct LoginTemplate {
-        secure_cookie_attribute: bool,
-    }
+/// Download the magnet links of all torrents as a text file with one link per line, which can be
+/// pasted into the add torrent page to add the torrents again.
+async fn export_magnets_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<impl IntoResponse, Response> {
+    let magnets = magnet_links(&state, session.data(), None)
+        .await
+        .map_err(IntoResponse::into_response)?;
 
-    LoginTemplate {
-        secure_cookie_attribute: state.config.security.secure_cookie_attribute,
-    }
-}
+    let lines = magnets
+        .into_iter()
+        .map(|magnet| Ok::<_, Infallible>(format!("{magnet}\n")));
 
-async fn login_post(
-    State(state): State<Arc<AppState>>,
-    Form(login): Form<LoginQuery>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let transmission_auth = transmission::rpc::TransmissionAuth {
-        username: login.username,
-        password: login.password,
-    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"transportal-magnets.txt\"",
+            ),
+        ],
+        axum::body::Body::from_stream(futures_util::stream::iter(lines)),
+    ))
+}
 
-    let rpc = transmission::rpc::TransmissionRpc::new(
-        state.config.connection.rpc_url.clone(),
-        transmission_auth,
+/// The magnet links of the torrents with the given hashes (or all torrents), sorted by the
+/// torrents' names so that lists of the same torrents can be compared.
+async fn magnet_links(
+    state: &AppState,
+    rpc: &transmission::rpc::TransmissionRpc,
+    ids: Option<Vec<String>>,
+) -> Result<Vec<String>, transmission::rpc::RpcError> {
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![
+            transmission::types::TorrentGetKey::Name,
+            transmission::types::TorrentGetKey::MagnetLink,
+        ],
+        ids,
     );
+    let torrent_resp = rpc
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
 
-    let session = session::Session::new(rpc);
-
-    let request = transmission::types::Request::session_get(vec![
-        transmission::types::SessionGetKey::Version,
-    ]);
-    let resp = session
-        .data()
-        .request::<transmission::types::SessionGetResponse>(&state.http_client, &request)
-        .await;
+    let mut torrents: Vec<(String, String)> = torrent_resp
+        .arguments
+        .torrents
+        .into_iter()
+        .filter_map(|torrent| {
+            let name = torrent.name().unwrap_or_default();
+            let magnet = torrent.magnet_link()?;
+            Some((name.to_string(), magnet.to_string()))
+        })
+        .collect();
 
-    if matches!(resp, Err(StatusCode::UNAUTHORIZED)) {
-        // could be wrong username/password
-        return Ok((StatusCode::UNAUTHORIZED, None, "Not authorized"));
-    }
+    torrents.sort();
 
-    if matches!(resp, Err(StatusCode::FORBIDDEN)) {
-        // could be the server connecting from a non-whitelisted IP
-        return Ok((StatusCode::FORBIDDEN, None, "Forbidden"));
-    }
+    Ok(torrents.into_iter().map(|(_name, magnet)| magnet).collect())
+}
 
-    // make sure to raise any other errors
-    let _resp = resp?;
+/// Download the torrent's magnet link as a file. Transmission only gives us the path of the
+/// ".torrent" file on the transmission host rather than its contents, so the magnet link is the
+/// best we can offer.
+async fn torrent_magnet_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, Response> {
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![
+            transmission::types::TorrentGetKey::Name,
+            transmission::types::TorrentGetKey::MagnetLink,
+        ],
+        Some(vec![hash.clone()]),
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
 
-    // if for some reason we can't compute the duration until the expiration, we'll just return a
-    // session cookie instead of a persistent cookie
-    let expire = session.expires().duration_since(SystemTime::now()).ok();
+    let Some(torrent) = torrent_resp.arguments.torrents.first() else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
 
-    let secret = state.sessions.new_session(session);
-    let secret = secret.as_cookie(state.config.security.secure_cookie_attribute, expire);
+    let name = torrent.name().unwrap_or_default();
+    let Some(magnet) = torrent.magnet_link() else {
+        return Err(StatusCode::BAD_GATEWAY.into_response());
+    };
 
-    let cookie = format!("session_secret={secret}");
-    let location = "/".to_string();
+    let disposition = format!(
+        "attachment; filename=\"{}.magnet\"",
+        template_helpers::safe_filename(name, "torrent"),
+    );
 
     Ok((
-        StatusCode::SEE_OTHER,
-        Some([(header::SET_COOKIE, cookie), (header::LOCATION, location)]),
-        "Success",
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        format!("{magnet}\n"),
     ))
 }
 
-async fn logout_post(
-    State(state): State<Arc<AppState>>,
-    headers: header::HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    let session_secret = session_secret_from_headers(&headers)?;
+/// The settings of a torrent that can be changed from the edit form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TorrentSettings {
+    labels: Vec<String>,
+    download_limited: bool,
+    /// In KB/s.
+    download_limit: u64,
+    upload_limited: bool,
+    /// In KB/s.
+    upload_limit: u64,
+    bandwidth_priority: i64,
+    honors_session_limits: bool,
+    /// `None` if transmission doesn't support sequential downloads.
+    sequential_download: Option<bool>,
+    /// `None` if transmission doesn't support bandwidth groups. An empty string means no group.
+    group: Option<String>,
+}
 
-    let _session = state
-        .sessions
-        .remove_session(session_secret)
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+impl TorrentSettings {
+    const KEYS: &'static [transmission::types::TorrentGetKey] = &[
+        transmission::types::TorrentGetKey::Labels,
+        transmission::types::TorrentGetKey::DownloadLimited,
+        transmission::types::TorrentGetKey::DownloadLimit,
+        transmission::types::TorrentGetKey::UploadLimited,
+        transmission::types::TorrentGetKey::UploadLimit,
+        transmission::types::TorrentGetKey::BandwidthPriority,
+        transmission::types::TorrentGetKey::HonorsSessionLimits,
+    ];
 
-    let cookie = "session_secret=; Secure; HttpOnly; SameSite=Lax; Max-Age=-1;";
+    fn from_torrent(torrent: &transmission::types::Torrent) -> Option<Self> {
+        use transmission::types::TorrentGetKey;
 
-    let html = r#"<meta http-equiv="refresh" content="0; url=/login"> Success. Redirecting."#;
+        let labels = torrent.get(&TorrentGetKey::Labels)?.as_array()?;
+        let labels = labels
+            .iter()
+            .map(|x| x.as_str().map(str::to_string))
+            .collect::<Option<_>>()?;
 
-    Ok(([(header::SET_COOKIE, cookie)], Html(html)))
+        Some(Self {
+            labels,
+            download_limited: torrent.get(&TorrentGetKey::DownloadLimited)?.as_bool()?,
+            download_limit: torrent.get(&TorrentGetKey::DownloadLimit)?.as_u64()?,
+            upload_limited: torrent.get(&TorrentGetKey::UploadLimited)?.as_bool()?,
+            upload_limit: torrent.get(&TorrentGetKey::UploadLimit)?.as_u64()?,
+            bandwidth_priority: torrent.get(&TorrentGetKey::BandwidthPriority)?.as_i64()?,
+            honors_session_limits: torrent
+                .get(&TorrentGetKey::HonorsSessionLimits)?
+                .as_bool()?,
+            sequential_download: torrent
+                .get(&TorrentGetKey::SequentialDownload)
+                .and_then(|x| x.as_bool()),
+            group: torrent
+                .get(&TorrentGetKey::Group)
+                .and_then(|x| x.as_str())
+                .map(str::to_string),
+        })
+    }
+
+    /// The fields that need to be set to go from these settings to the new settings. Unchanged
+    /// fields are omitted so that if these are the settings the form was rendered with, we don't
+    /// overwrite changes made elsewhere while the form was open.
+    fn changes(&self, new: &Self) -> transmission::types::TorrentSetArgs {
+        let mut args = transmission::types::TorrentSetArgs::new();
+
+        if self.bandwidth_priority != new.bandwidth_priority {
+            args = args.bandwidth_priority(new.bandwidth_priority);
+        }
+        if self.download_limit != new.download_limit {
+            args = args.download_limit(new.download_limit);
+        }
+        if self.download_limited != new.download_limited {
+            args = args.download_limited(new.download_limited);
+        }
+        if self.honors_session_limits != new.honors_session_limits {
+            args = args.honors_session_limits(new.honors_session_limits);
+        }
+        if self.labels != new.labels {
+            args = args.labels(new.labels.clone());
+        }
+        if self.upload_limit != new.upload_limit {
+            args = args.upload_limit(new.upload_limit);
+        }
+        if self.upload_limited != new.upload_limited {
+            args = args.upload_limited(new.upload_limited);
+        }
+        // only set if transmission supports it (in which case the current value is known)
+        if let (Some(old), Some(new)) = (self.sequential_download, new.sequential_download) {
+            if old != new {
+                args = args.sequential_download(new);
+            }
+        }
+        if let (Some(old), Some(new)) = (&self.group, &new.group) {
+            if old != new {
+                args = args.group(new.clone());
+            }
+        }
+
+        args
+    }
 }
 
-async fn start_torrent_post(
+#[derive(Debug, Clone, Deserialize)]
+struct EditTorrentForm {
+    /// Comma-separated.
+    labels: String,
+    download_limited: Option<String>,
+    download_limit: u64,
+    upload_limited: Option<String>,
+    upload_limit: u64,
+    bandwidth_priority: i64,
+    honors_session_limits: Option<String>,
+    sequential_download: Option<String>,
+    /// An empty string removes the torrent from its group.
+    group: Option<String>,
+    // the settings that the form was rendered with, from hidden inputs
+    original_labels: String,
+    original_download_limited: bool,
+    original_download_limit: u64,
+    original_upload_limited: bool,
+    original_upload_limit: u64,
+    original_bandwidth_priority: i64,
+    original_honors_session_limits: bool,
+    original_sequential_download: Option<bool>,
+    original_group: Option<String>,
+}
+
+impl EditTorrentForm {
+    fn parse_labels(labels: &str) -> Vec<String> {
+        labels
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The settings that the form was rendered with.
+    fn original(&self) -> TorrentSettings {
+        TorrentSettings {
+            labels: Self::parse_labels(&self.original_labels),
+            download_limited: self.original_download_limited,
+            download_limit: self.original_download_limit,
+            upload_limited: self.original_upload_limited,
+            upload_limit: self.original_upload_limit,
+            bandwidth_priority: self.original_bandwidth_priority,
+            honors_session_limits: self.original_honors_session_limits,
+            sequential_download: self.original_sequential_download,
+            group: self.original_group.clone(),
+        }
+    }
+
+    /// The settings that the user submitted.
+    fn settings(&self) -> Option<TorrentSettings> {
+        if !(-1..=1).contains(&self.bandwidth_priority) {
+            return None;
+        }
+
+        Some(TorrentSettings {
+            labels: Self::parse_labels(&self.labels),
+            download_limited: self.download_limited.is_some(),
+            download_limit: self.download_limit,
+            upload_limited: self.upload_limited.is_some(),
+            upload_limit: self.upload_limit,
+            bandwidth_priority: self.bandwidth_priority,
+            honors_session_limits: self.honors_session_limits.is_some(),
+            sequential_download: Some(self.sequential_download.is_some()),
+            group: self.group.as_deref().map(str::trim).map(str::to_string),
+        })
+    }
+}
+
+async fn edit_torrent_get(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Form(TorrentQuery { hash }): Form<TorrentQuery>,
-) -> Result<(), StatusCode> {
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Template)]
+    #[template(path = "edit-torrent.html")]
+    struct EditTorrentTemplate {
+        display_name: String,
+        hash: String,
+        name: String,
+        settings: TorrentSettings,
+    }
+
+    let (name, settings) = torrent_settings(session.data(), &state.http_client, &hash)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(EditTorrentTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        hash,
+        name,
+        settings,
+    })
+}
+
+async fn edit_torrent_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+    Form(form): Form<EditTorrentForm>,
+) -> Result<impl IntoResponse, Response> {
     #[derive(Deserialize)]
     struct Empty {}
 
-    let request = transmission::types::Request::torrent_start(Some(vec![hash]));
-    let _torrent_resp = session
-        .data()
-        .request::<Empty>(&state.http_client, &request)
-        .await?;
+    let new_settings = form
+        .settings()
+        .ok_or(StatusCode::BAD_REQUEST.into_response())?;
 
-    session.data().notify.notify_waiters();
+    // compare against the settings the form was rendered with rather than the current settings,
+    // otherwise fields that the user didn't touch would be reverted if they were changed elsewhere
+    let settings = form.original();
 
-    Ok(())
+    // the group is only known if transmission supports groups, so a non-empty group here means the
+    // user is trying to set one on a server that can't store it
+    if settings.group.is_none() && new_settings.group.as_deref().is_some_and(|x| !x.is_empty()) {
+        session
+            .data()
+            .require(
+                &state.http_client,
+                transmission::types::RpcFeature::BandwidthGroups,
+            )
+            .await?;
+    }
+
+    let changes = settings.changes(&new_settings);
+
+    if !changes.is_empty() {
+        let request = transmission::types::Request::torrent_set(Some(vec![hash.clone()]), changes);
+        let _torrent_resp = session
+            .data()
+            .request::<Empty>(&state.http_client, &request)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        session.data().notify.notify_waiters();
+    }
+
+    Ok((
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, format!("/torrent/{hash}"))],
+    ))
 }
 
-async fn pause_torrent_post(
+async fn settings_get(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Form(TorrentQuery { hash }): Form<TorrentQuery>,
-) -> Result<(), StatusCode> {
-    #[derive(Deserialize)]
-    struct Empty {}
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Template)]
+    #[template(path = "settings.html")]
+    struct SettingsTemplate {
+        display_name: String,
+        blocklist_enabled: bool,
+        blocklist_size: u64,
+        blocklist_url: String,
+        peer_port: u64,
+        port_forwarding_enabled: bool,
+        allow_transmission_shutdown: bool,
+        is_admin: bool,
+        bandwidth_groups: bool,
+    }
 
-    let request = transmission::types::Request::torrent_stop(Some(vec![hash]));
-    let _torrent_resp = session
+    let version = session.data().version(&state.http_client).await?;
+
+    let request = transmission::types::Request::session_get(vec![
+        transmission::types::SessionGetKey::BlocklistEnabled,
+        transmission::types::SessionGetKey::BlocklistSize,
+        transmission::types::SessionGetKey::BlocklistUrl,
+        transmission::types::SessionGetKey::PeerPort,
+        transmission::types::SessionGetKey::PortForwardingEnabled,
+    ]);
+    let session_resp = session
         .data()
-        .request::<Empty>(&state.http_client, &request)
+        .request::<transmission::types::SessionGetResponse>(&state.http_client, &request)
         .await?;
+    let get = |key| session_resp.arguments.0.get(&key);
 
-    session.data().notify.notify_waiters();
+    Ok(SettingsTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        allow_transmission_shutdown: state.config.security.allow_transmission_shutdown,
+        is_admin: is_admin(&state, &session).await,
+        bandwidth_groups: version.supports(transmission::types::RpcFeature::BandwidthGroups),
+        blocklist_enabled: get(transmission::types::SessionGetKey::BlocklistEnabled)
+            .and_then(|x| x.as_bool())
+            .ok_or(StatusCode::BAD_GATEWAY)?,
+        blocklist_size: get(transmission::types::SessionGetKey::BlocklistSize)
+            .and_then(|x| x.as_u64())
+            .ok_or(StatusCode::BAD_GATEWAY)?,
+        blocklist_url: get(transmission::types::SessionGetKey::BlocklistUrl)
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        peer_port: get(transmission::types::SessionGetKey::PeerPort)
+            .and_then(|x| x.as_u64())
+            .ok_or(StatusCode::BAD_GATEWAY)?,
+        port_forwarding_enabled: get(transmission::types::SessionGetKey::PortForwardingEnabled)
+            .and_then(|x| x.as_bool())
+            .unwrap_or_default(),
+    })
+}
 
-    Ok(())
+#[derive(Debug, Clone, Deserialize)]
+struct BlocklistForm {
+    blocklist_enabled: Option<String>,
+    blocklist_url: String,
 }
 
-async fn verify_torrent_post(
+async fn blocklist_post(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Form(TorrentQuery { hash }): Form<TorrentQuery>,
-) -> Result<(), StatusCode> {
+    Form(form): Form<BlocklistForm>,
+) -> Result<impl IntoResponse, StatusCode> {
     #[derive(Deserialize)]
     struct Empty {}
 
-    let request = transmission::types::Request::torrent_verify(Some(vec![hash]));
-    let _torrent_resp = session
+    let enabled = match form.blocklist_enabled.as_deref() {
+        Some("on") => true,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+        None => false,
+    };
+
+    let request = transmission::types::Request::session_set(
+        transmission::types::SessionSetArgs::new()
+            .blocklist_enabled(enabled)
+            .blocklist_url(form.blocklist_url.trim().to_string()),
+    );
+    let _session_resp = session
         .data()
         .request::<Empty>(&state.http_client, &request)
         .await?;
 
-    session.data().notify.notify_waiters();
-
-    Ok(())
+    Ok((StatusCode::SEE_OTHER, [(header::LOCATION, "/settings")]))
 }
 
-async fn add_torrent_get(
-    // needed to verify that the user is logged in
-    SessionArc(_session): SessionArc,
+async fn network_settings_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
 ) -> Result<impl IntoResponse, StatusCode> {
+    use transmission::types::SessionGetKey;
+
     #[derive(Template)]
-    #[template(path = "add-torrent.html")]
-    struct AddTorrentTemplate;
+    #[template(path = "network.html")]
+    struct NetworkSettingsTemplate {
+        display_name: String,
+        peer_limit_global: u64,
+        peer_limit_per_torrent: u64,
+        dht_enabled: bool,
+        pex_enabled: bool,
+        lpd_enabled: bool,
+        utp_enabled: bool,
+        encryption: transmission::types::Encryption,
+    }
+
+    let request = transmission::types::Request::session_get(vec![
+        SessionGetKey::PeerLimitGlobal,
+        SessionGetKey::PeerLimitPerTorrent,
+        SessionGetKey::DhtEnabled,
+        SessionGetKey::PexEnabled,
+        SessionGetKey::LpdEnabled,
+        SessionGetKey::UtpEnabled,
+        SessionGetKey::Encryption,
+    ]);
+    let session_resp = session
+        .data()
+        .request::<transmission::types::SessionGetResponse>(&state.http_client, &request)
+        .await?;
+    let get = |key| session_resp.arguments.0.get(&key);
+    let get_u64 = |key| {
+        get(key)
+            .and_then(|x| x.as_u64())
+            .ok_or(StatusCode::BAD_GATEWAY)
+    };
+    let get_bool = |key| {
+        get(key)
+            .and_then(|x| x.as_bool())
+            .ok_or(StatusCode::BAD_GATEWAY)
+    };
 
-    Ok(AddTorrentTemplate)
+    Ok(NetworkSettingsTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        peer_limit_global: get_u64(SessionGetKey::PeerLimitGlobal)?,
+        peer_limit_per_torrent: get_u64(SessionGetKey::PeerLimitPerTorrent)?,
+        dht_enabled: get_bool(SessionGetKey::DhtEnabled)?,
+        pex_enabled: get_bool(SessionGetKey::PexEnabled)?,
+        lpd_enabled: get_bool(SessionGetKey::LpdEnabled)?,
+        utp_enabled: get_bool(SessionGetKey::UtpEnabled)?,
+        encryption: get(SessionGetKey::Encryption)
+            .and_then(|x| x.as_str())
+            .and_then(|x| x.parse().ok())
+            .ok_or(StatusCode::BAD_GATEWAY)?,
+    })
 }
 
-async fn add_torrent_post(
+#[derive(Debug, Clone, Deserialize)]
+struct NetworkSettingsForm {
+    dht_enabled: Option<String>,
+    pex_enabled: Option<String>,
+    lpd_enabled: Option<String>,
+    utp_enabled: Option<String>,
+    encryption: String,
+}
+
+async fn network_settings_post(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Form(AddTorrentQuery { magnet, paused }): Form<AddTorrentQuery>,
+    Form(form): Form<NetworkSettingsForm>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    if !magnet.starts_with("magnet:?xt=urn:btih:") {
-        println!(r#"Incorrect format for magnet link "{magnet}""#);
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    #[derive(Deserialize)]
+    struct Empty {}
 
-    let paused = match paused.as_deref() {
-        Some("on") => true,
-        Some(_) => return Err(StatusCode::BAD_REQUEST),
-        None => false,
-    };
+    let encryption = form.encryption.parse().or(Err(StatusCode::BAD_REQUEST))?;
 
-    let request = transmission::types::Request::torrent_add(
-        transmission::types::TorrentAddRequired::Filename(magnet),
-        /* paused= */ paused,
+    let request = transmission::types::Request::session_set(
+        transmission::types::SessionSetArgs::new()
+            .dht_enabled(form.dht_enabled.is_some())
+            .pex_enabled(form.pex_enabled.is_some())
+            .lpd_enabled(form.lpd_enabled.is_some())
+            .utp_enabled(form.utp_enabled.is_some())
+            .encryption(encryption),
     );
+    let _session_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await?;
 
-    let resp = session
+    Ok((
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, "/settings/network")],
+    ))
+}
+
+async fn groups_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<impl IntoResponse, Response> {
+    #[derive(Template)]
+    #[template(path = "groups.html")]
+    struct GroupsTemplate {
+        display_name: String,
+        groups: Vec<transmission::types::BandwidthGroup>,
+    }
+
+    session
         .data()
-        .request::<transmission::types::TorrentAddResponse>(&state.http_client, &request)
+        .require(
+            &state.http_client,
+            transmission::types::RpcFeature::BandwidthGroups,
+        )
         .await?;
 
-    session.data().notify.notify_waiters();
+    let request = transmission::types::Request::group_get(None);
+    let resp = session
+        .data()
+        .request::<transmission::types::GroupGetResponse>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let mut groups = resp.arguments.group;
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(GroupsTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        groups,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GroupForm {
+    name: String,
+    honors_session_limits: Option<String>,
+    speed_limit_down_enabled: Option<String>,
+    /// In KB/s.
+    speed_limit_down: u64,
+    speed_limit_up_enabled: Option<String>,
+    /// In KB/s.
+    speed_limit_up: u64,
+}
+
+/// Create or update a bandwidth group.
+async fn groups_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(form): Form<GroupForm>,
+) -> Result<impl IntoResponse, Response> {
+    #[derive(Deserialize)]
+    struct Empty {}
 
-    // make sure we're not injecting weird content into the header
-    let hash = resp.arguments.hash_string();
-    assert!(hash.chars().all(char::is_alphanumeric));
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The group name can't be empty").into_response());
+    }
+
+    session
+        .data()
+        .require(
+            &state.http_client,
+            transmission::types::RpcFeature::BandwidthGroups,
+        )
+        .await?;
 
-    let location = format!("/torrent/{hash}");
+    let request = transmission::types::Request::group_set(transmission::types::BandwidthGroup {
+        name: name.to_string(),
+        honors_session_limits: form.honors_session_limits.is_some(),
+        speed_limit_down_enabled: form.speed_limit_down_enabled.is_some(),
+        speed_limit_down: form.speed_limit_down,
+        speed_limit_up_enabled: form.speed_limit_up_enabled.is_some(),
+        speed_limit_up: form.speed_limit_up,
+    });
+    let _resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
 
     Ok((
         StatusCode::SEE_OTHER,
-        Some([(header::LOCATION, location)]),
-        "Success",
+        [(header::LOCATION, "/settings/groups")],
     ))
 }
 
-async fn stub_torrents_get(
+/// Have transmission download the blocklist from the configured URL. Responds with the number of
+/// rules in the new blocklist.
+async fn blocklist_update_post(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Query(TorrentListQuery {
-        filter,
-        sort_direction: _,
-    }): Query<TorrentListQuery>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let filter_str = filter.as_deref();
-    let torrents = torrent_list(session.data(), &state.http_client, filter_str).await?;
+) -> Result<String, transmission::rpc::RpcError> {
+    let request = transmission::types::Request::blocklist_update();
+    let resp = session
+        .data()
+        .request::<transmission::types::BlocklistUpdateResponse>(&state.http_client, &request)
+        .await?;
 
-    Ok(TorrentListStubTemplate {
-        filter,
-        partial: torrents,
-    })
+    Ok(resp.arguments.blocklist_size.to_string())
 }
 
-async fn stub_torrent_get(
+/// Have transmission check whether its peer port is reachable from the internet. This can take a
+/// few seconds since transmission asks an external service.
+async fn port_test_post(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Form(TorrentQuery { hash }): Form<TorrentQuery>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let torrent = torrent_details(session.data(), &state.http_client, &hash).await?;
-
-    let Some(torrent) = torrent else {
-        return Err(StatusCode::NOT_FOUND);
-    };
+) -> Result<&'static str, transmission::rpc::RpcError> {
+    let request = transmission::types::Request::port_test();
+    let resp = session
+        .data()
+        .request::<transmission::types::PortTestResponse>(&state.http_client, &request)
+        .await?;
 
-    Ok(TorrentStubTemplate {
-        hash,
-        partial: torrent,
+    Ok(if resp.arguments.port_is_open {
+        "Open"
+    } else {
+        "Closed"
     })
 }
 
-async fn sse_torrents_get(
+#[derive(Debug, Clone, Deserialize)]
+struct ShutdownForm {
+    /// Must be "shutdown", so that the daemon isn't shut down by an accidental request.
+    confirm: String,
+}
+
+/// Shut down the transmission daemon. Only allowed for admin users, and only if enabled in the
+/// configuration.
+async fn shutdown_transmission_post(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Query(TorrentListQuery {
-        filter,
-        sort_direction: _,
-    }): Query<TorrentListQuery>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let stream = futures_util::stream::unfold(
-        (session, state, filter, None),
-        |(session, state, filter, last)| async move {
-            let html = loop {
-                let interval = Duration::from_millis(state.config.performance.poll_interval_ms);
-                let _ = tokio::time::timeout(interval, session.data().notify.notified()).await;
+    Form(ShutdownForm { confirm }): Form<ShutdownForm>,
+) -> Result<&'static str, Response> {
+    #[derive(Deserialize)]
+    struct Empty {}
 
-                if session.expired() {
-                    return None;
-                }
+    if !state.config.security.allow_transmission_shutdown {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
 
-                let filter = filter.as_deref();
-                let torrents = torrent_list(session.data(), &state.http_client, filter)
-                    .await
-                    .ok()?;
+    if !is_admin(&state, &session).await {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
 
-                let html = torrents.render().unwrap();
+    if confirm != "shutdown" {
+        return Err((StatusCode::BAD_REQUEST, "The shutdown wasn't confirmed").into_response());
+    }
 
-                if let Some(ref last) = last {
-                    if html != *last {
-                        break html;
-                    }
-                } else {
-                    break html;
-                }
-            };
+    println!(
+        "Shutting down transmission at the request of user {:?}",
+        session.data().auth().username,
+    );
 
-            let event = Event::default().event("list").data(html.clone());
-            Some((event, (session, state, filter, Some(html))))
-        },
-    )
-    .map(Ok);
+    let request = transmission::types::Request::session_close();
+    session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
 
-    Sse::new(stream).keep_alive(
-        KeepAlive::new()
-            .interval(Duration::from_secs(10))
-            .text("keep-alive-text"),
-    )
+    Ok("Transmission is shutting down")
 }
 
-async fn sse_torrent_get(
+/// Whether the session's user is listed in the `admin_users` config option.
+async fn is_admin(
+    state: &AppState,
+    session: &session::Session<transmission::rpc::TransmissionRpc>,
+) -> bool {
+    let username = &session.data().auth().username;
+    if !state.config.security.admin_users.contains(username) {
+        return false;
+    }
+
+    // if transmission doesn't require credentials then it never checked the username, so anyone
+    // could have logged in with an admin's username
+    state.rpc_auth_required().await == Some(true)
+}
+
+/// List all active sessions. Only allowed for admin users.
+async fn sessions_get(
     State(state): State<Arc<AppState>>,
     SessionArc(session): SessionArc,
-    Query(query): Query<TorrentQuery>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let stream = futures_util::stream::unfold(
-        (session, state, query, None),
-        |(session, state, query, last)| async move {
-            let html = loop {
-                let interval = Duration::from_millis(state.config.performance.poll_interval_ms);
-                let _ = tokio::time::timeout(interval, session.data().notify.notified()).await;
+) -> Result<impl IntoResponse, StatusCode> {
+    struct SessionRow {
+        id: u64,
+        username: String,
+        age: String,
+        expires_in: String,
+        last_seen: String,
+        streams: usize,
+        current: bool,
+    }
 
-                if session.expired() {
-                    return None;
-                }
+    #[derive(Template)]
+    #[template(path = "sessions.html")]
+    struct SessionsTemplate {
+        display_name: String,
+        sessions: Vec<SessionRow>,
+    }
 
-                let torrent = torrent_details(session.data(), &state.http_client, &query.hash)
-                    .await
-                    .ok()?;
+    if !is_admin(&state, &session).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-                let Some(torrent) = torrent else {
-                    return Some((
-                        Event::default().event("removed").data("<b>Removed</b>"),
-                        (session, state, query, None),
-                    ));
-                };
+    let now = SystemTime::now();
+    let between = |earlier: SystemTime, later: SystemTime| {
+        let secs = later.duration_since(earlier).unwrap_or_default().as_secs();
+        template_helpers::duration(secs)
+    };
 
-                let html = torrent.render().unwrap();
+    let mut sessions: Vec<_> = state.sessions.sessions().collect();
 
-                if let Some(ref last) = last {
-                    if html != *last {
-                        break html;
-                    }
-                } else {
-                    break html;
-                }
-            };
+    // most recently used first
+    sessions.sort_by_key(|x| (x.last_seen(), x.id()));
 
-            let event = Event::default().event("details").data(html.clone());
-            Some((event, (session, state, query, Some(html))))
-        },
-    )
-    .map(Ok);
+    let sessions = sessions
+        .into_iter()
+        .map(|x| SessionRow {
+            id: x.id(),
+            username: x.data().auth().username.clone(),
+            age: between(x.created(), now),
+            expires_in: between(now, x.expires()),
+            last_seen: template_helpers::duration(x.last_seen().as_secs()),
+            streams: x.streams(),
+            current: x.id() == session.id(),
+        })
+        .collect();
 
-    Sse::new(stream).keep_alive(
-        KeepAlive::new()
-            .interval(Duration::from_secs(10))
-            .text("keep-alive-text"),
-    )
+    Ok(SessionsTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        sessions,
+    })
 }
 
-async fn torrent_list(
-    rpc: &transmission::rpc::TransmissionRpc,
-    client: &reqwest::Client,
-    filter: Option<&str>,
-) -> Result<TorrentListPartialTemplate, StatusCode> {
-    let request = transmission::types::Request::torrent_get(
-        transmission::types::TorrentGetFormat::Objects,
-        vec![
-            transmission::types::TorrentGetKey::DateCreated,
-            transmission::types::TorrentGetKey::AddedDate,
-            transmission::types::TorrentGetKey::Id,
-            transmission::types::TorrentGetKey::Name,
-            transmission::types::TorrentGetKey::HashString,
-            transmission::types::TorrentGetKey::PercentComplete,
-            transmission::types::TorrentGetKey::PercentDone,
-            transmission::types::TorrentGetKey::TotalSize,
-            transmission::types::TorrentGetKey::Eta,
-            transmission::types::TorrentGetKey::IsFinished,
-            transmission::types::TorrentGetKey::Wanted,
-            transmission::types::TorrentGetKey::LeftUntilDone,
-            transmission::types::TorrentGetKey::SizeWhenDone,
-            transmission::types::TorrentGetKey::Status,
-            transmission::types::TorrentGetKey::Labels,
-        ],
+#[derive(Debug, Clone, Deserialize)]
+struct RevokeSessionForm {
+    id: u64,
+}
+
+/// Remove a session, which logs out whoever is using it. Only allowed for admin users.
+async fn sessions_revoke_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(RevokeSessionForm { id }): Form<RevokeSessionForm>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !is_admin(&state, &session).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let revoked = state
+        .sessions
+        .remove_session_by_id(id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    println!(
+        "Session of user {:?} was revoked by user {:?}",
+        revoked.data().auth().username,
+        session.data().auth().username,
+    );
+
+    Ok((
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, "/settings/sessions")],
+    ))
+}
+
+/// A change to which of a torrent's files are downloaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FileSelection {
+    All,
+    None,
+    /// Download the files that currently aren't wanted, and vice versa.
+    Invert,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FileSelectionForm {
+    select: FileSelection,
+}
+
+async fn torrent_files_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+    Form(FileSelectionForm { select }): Form<FileSelectionForm>,
+) -> Result<(), Response> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![
+            transmission::types::TorrentGetKey::FileCount,
+            transmission::types::TorrentGetKey::Wanted,
+        ],
+        Some(vec![hash.clone()]),
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let Some(torrent) = torrent_resp.arguments.torrents.first() else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let wanted: Vec<bool> = torrent
+        .get(&transmission::types::TorrentGetKey::Wanted)
+        .and_then(|x| x.as_array())
+        .map(|x| {
+            x.iter()
+                .map(template_helpers::json_num_to_bool)
+                .collect::<Option<_>>()
+        })
+        .unwrap_or_default()
+        .unwrap_or_default();
+
+    // "file-count" was added in rpc version 17, so fall back to the length of "wanted"
+    let file_count = torrent
+        .get(&transmission::types::TorrentGetKey::FileCount)
+        .and_then(|x| x.as_u64())
+        .map(|x| x as usize)
+        .unwrap_or(wanted.len());
+
+    let all = || (0..file_count).collect::<Vec<_>>();
+    let args = transmission::types::TorrentSetArgs::new();
+    let args = match select {
+        FileSelection::All => args.files_wanted(all()),
+        FileSelection::None => args.files_unwanted(all()),
+        FileSelection::Invert => {
+            if wanted.len() != file_count {
+                return Err(StatusCode::BAD_GATEWAY.into_response());
+            }
+
+            let (unwanted, wanted): (Vec<_>, Vec<_>) = (0..file_count).partition(|i| wanted[*i]);
+            args.files_wanted(wanted).files_unwanted(unwanted)
+        }
+    };
+
+    if args.is_empty() {
+        return Ok(());
+    }
+
+    let request = transmission::types::Request::torrent_set(Some(vec![hash]), args);
+    let _torrent_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+/// Transmission stores the idle limit as a 16-bit number.
+const MAX_SEED_IDLE_MINUTES: u64 = u16::MAX as u64;
+
+#[derive(Debug, Clone, Deserialize)]
+struct SeedIdleForm {
+    minutes: u64,
+}
+
+/// Stop seeding the torrent once it has been idle for the given number of minutes, regardless of
+/// the session's idle limit.
+async fn torrent_seed_idle_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+    Form(SeedIdleForm { minutes }): Form<SeedIdleForm>,
+) -> Result<(), StatusCode> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    if !(1..=MAX_SEED_IDLE_MINUTES).contains(&minutes) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let args = transmission::types::TorrentSetArgs::new()
+        .seed_idle_limit(minutes)
+        .seed_idle_mode(transmission::types::IdleLimitMode::Single);
+    let request = transmission::types::Request::torrent_set(Some(vec![hash]), args);
+    let _torrent_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await?;
+
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PriorityForm {
+    priority: i64,
+}
+
+/// Set the torrent's bandwidth priority, where -1 is low, 0 is normal, and 1 is high.
+async fn torrent_priority_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+    Form(PriorityForm { priority }): Form<PriorityForm>,
+) -> Result<(), StatusCode> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    if !(-1..=1).contains(&priority) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let args = transmission::types::TorrentSetArgs::new().bandwidth_priority(priority);
+    let request = transmission::types::Request::torrent_set(Some(vec![hash]), args);
+    let _torrent_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await?;
+
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+/// Pin the torrent to the top of the torrent list, or unpin it. Favorites are stored in the session
+/// since transmission has no equivalent.
+async fn torrent_favorite_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+) -> Result<(), StatusCode> {
+    if !is_info_hash(&hash) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // transmission's hashes are lowercase, and pins are matched against them
+    let hash = hash.to_ascii_lowercase();
+
+    // only pin torrents that exist, so that the favorites can't grow without limit
+    if !session.favorites().contains(&hash) {
+        let request = transmission::types::Request::torrent_get(
+            transmission::types::TorrentGetFormat::Objects,
+            vec![transmission::types::TorrentGetKey::HashString],
+            Some(vec![hash.clone()]),
+        );
+        let torrent_resp = session
+            .data()
+            .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+            .await?;
+
+        if torrent_resp.arguments.torrents.is_empty() {
+            return Err(StatusCode::NOT_FOUND);
+        }
+    }
+
+    session.toggle_favorite(&hash);
+
+    // the stream sends the reordered torrent list
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+/// Whether the string is an info hash, which is 40 (v1) or 64 (v2) hex characters.
+fn is_info_hash(hash: &str) -> bool {
+    matches!(hash.len(), 40 | 64) && hash.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum QueueDirection {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct QueueForm {
+    direction: QueueDirection,
+}
+
+/// Move the torrent one position up or down in the queue.
+async fn torrent_queue_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Path(hash): Path<String>,
+    Form(QueueForm { direction }): Form<QueueForm>,
+) -> Result<(), StatusCode> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    let ids = Some(vec![hash]);
+    let request = match direction {
+        QueueDirection::Up => transmission::types::Request::queue_move_up(ids),
+        QueueDirection::Down => transmission::types::Request::queue_move_down(ids),
+    };
+    let _torrent_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await?;
+
+    // moving a torrent also changes the queue position of other torrents, but the SSE stream
+    // requests all torrents after being notified
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+/// Get the name and current settings of a torrent.
+async fn torrent_settings(
+    rpc: &transmission::rpc::TransmissionRpc,
+    client: &reqwest::Client,
+    hash: &str,
+) -> Result<Option<(String, TorrentSettings)>, StatusCode> {
+    let mut keys = vec![transmission::types::TorrentGetKey::Name];
+    keys.extend_from_slice(TorrentSettings::KEYS);
+
+    // older versions of transmission ignore unknown fields, so we'd have no way to tell whether
+    // setting it worked
+    let version = rpc.version(client).await?;
+    if version.supports(transmission::types::RpcFeature::SequentialDownload) {
+        keys.push(transmission::types::TorrentGetKey::SequentialDownload);
+    }
+    if version.supports(transmission::types::RpcFeature::BandwidthGroups) {
+        keys.push(transmission::types::TorrentGetKey::Group);
+    }
+
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        keys,
+        Some(vec![hash.to_string()]),
+    );
+    let torrent_resp = rpc
+        .request::<transmission::types::TorrentGetResponse>(client, &request)
+        .await?;
+
+    let Some(torrent) = torrent_resp.arguments.torrents.first() else {
+        return Ok(None);
+    };
+
+    let name = torrent.name().unwrap_or_default().to_string();
+    let mut settings = TorrentSettings::from_torrent(torrent).ok_or(StatusCode::BAD_GATEWAY)?;
+
+    // transmission may omit the group of a torrent that has none, but the group must be known for
+    // it to be changed
+    if version.supports(transmission::types::RpcFeature::BandwidthGroups) {
+        settings.group.get_or_insert_with(String::new);
+    }
+
+    Ok(Some((name, settings)))
+}
+
+async fn login_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    #[derive(Template)]
+    #[template(path = "login.html")]
+    struct LoginTemplate {
+        display_name: String,
+        secure_cookie_attribute: bool,
+        login_message: String,
+    }
+
+    LoginTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        secure_cookie_attribute: state.config.security.secure_cookie_attribute,
+        login_message: state.config.security.login_message.clone(),
+    }
+}
+
+async fn login_post(
+    State(state): State<Arc<AppState>>,
+    headers: header::HeaderMap,
+    Form(login): Form<LoginQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let transmission_auth = transmission::rpc::TransmissionAuth {
+        username: login.username,
+        password: login.password,
+    };
+
+    let secure_cookie_attribute = state.config.security.secure_cookie_attribute;
+    let force_session_cookies = state.config.security.force_session_cookies;
+    let success = |secret: session::SessionSecret, expires: SystemTime| {
+        // if for some reason we can't compute the duration until the expiration, we'll just
+        // return a session cookie instead of a persistent cookie
+        let expire = expires.duration_since(SystemTime::now()).ok();
+        let expire = expire.filter(|_| !force_session_cookies);
+        let secret = secret.as_cookie(secure_cookie_attribute, expire);
+
+        let cookie = format!("session_secret={secret}");
+        let location = "/".to_string();
+
+        (
+            StatusCode::SEE_OTHER,
+            Some([(header::SET_COOKIE, cookie), (header::LOCATION, location)]),
+            "Success",
+        )
+    };
+
+    // if the browser still has a valid session for the same credentials (for example if the user
+    // logged in again from another tab), keep using it so that its open streams keep working
+    if state.config.security.reuse_sessions {
+        let existing = session_cookie_from_headers(&state, &headers).ok();
+
+        if let Some((secret, session)) = existing {
+            if *session.data().auth() == transmission_auth {
+                return Ok(success(secret, session.max_expires()));
+            }
+        }
+    }
+
+    let rpc = transmission::rpc::TransmissionRpc::new(
+        state.config.connection.rpc_url.clone(),
+        transmission_auth,
+        state.rpc_auth.clone(),
+        state.config.connection.rpc_session_id.clone(),
+        Arc::clone(&state.rpc_limiter),
+    );
+
+    let idle_timeout = state.config.security.idle_timeout_secs;
+    let session =
+        session::Session::new(rpc).with_idle_timeout(idle_timeout.map(Duration::from_secs));
+
+    // this checks that the credentials work, and caches the version for later
+    let resp = session.data().version(&state.http_client).await;
+
+    if matches!(resp, Err(transmission::rpc::RpcError::Unauthorized)) {
+        // could be wrong username/password
+        return Ok((StatusCode::UNAUTHORIZED, None, "Not authorized"));
+    }
+
+    if matches!(resp, Err(transmission::rpc::RpcError::Forbidden)) {
+        // could be the server connecting from a non-whitelisted IP
+        return Ok((StatusCode::FORBIDDEN, None, "Forbidden"));
+    }
+
+    // make sure to raise any other errors
+    let _resp = resp?;
+
+    if let Some(window) = state.config.security.sliding_expiry_secs {
+        session.refresh_expiry(Duration::from_secs(window));
+    }
+
+    // the session's expiration may be extended, so the cookie should last as long as the session
+    // possibly could
+    let expires = session.max_expires();
+    let secret = state.sessions.new_session(session);
+
+    Ok(success(secret, expires))
+}
+
+async fn logout_post(
+    State(state): State<Arc<AppState>>,
+    headers: header::HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (session_secret, _session) = session_cookie_from_headers(&state, &headers)?;
+
+    let _session = state
+        .sessions
+        .remove_session(session_secret)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    // must have the same path as the session cookie to replace it
+    let cookie = "session_secret=; Path=/; Secure; HttpOnly; SameSite=Lax; Max-Age=-1";
+
+    let html = r#"<meta http-equiv="refresh" content="0; url=/login"> Success. Redirecting."#;
+
+    Ok(([(header::SET_COOKIE, cookie)], Html(html)))
+}
+
+async fn start_torrent_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(TorrentQuery { hash }): Form<TorrentQuery>,
+) -> Result<(), transmission::rpc::RpcError> {
+    torrent_action(&state, &session, TorrentAction::Start, vec![hash]).await
+}
+
+async fn pause_torrent_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(TorrentQuery { hash }): Form<TorrentQuery>,
+) -> Result<(), transmission::rpc::RpcError> {
+    torrent_action(&state, &session, TorrentAction::Pause, vec![hash]).await
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VerifyTorrentForm {
+    hash: String,
+    /// Verify the torrent even if it's running.
+    #[serde(default)]
+    force: bool,
+}
+
+/// Verify the torrent. Verifying a running torrent interrupts its transfers, so this is refused
+/// (with a 409 status and an explanation) unless the torrent is paused or `force` is set.
+async fn verify_torrent_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(VerifyTorrentForm { hash, force }): Form<VerifyTorrentForm>,
+) -> Result<(), Response> {
+    if !force {
+        let request = transmission::types::Request::torrent_get(
+            transmission::types::TorrentGetFormat::Objects,
+            vec![transmission::types::TorrentGetKey::Status],
+            Some(vec![hash.clone()]),
+        );
+        let torrent_resp = session
+            .data()
+            .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let Some(torrent) = torrent_resp.arguments.torrents.first() else {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+
+        if torrent.status() != Some(transmission::types::TorrentStatus::Stopped) {
+            let msg = "The torrent is running, and verifying it will interrupt its downloads and uploads. Pause the torrent first, or verify it anyway.";
+            return Err((StatusCode::CONFLICT, msg).into_response());
+        }
+    }
+
+    torrent_action(&state, &session, TorrentAction::Verify, vec![hash])
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+/// Verify all torrents that have an error, for example after the disk they're stored on was
+/// remounted. Responds with the number of torrents that were queued for verification.
+async fn verify_errored_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<String, transmission::rpc::RpcError> {
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![
+            transmission::types::TorrentGetKey::HashString,
+            transmission::types::TorrentGetKey::Error,
+        ],
+        None,
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
+
+    let ids: Vec<String> = torrent_resp
+        .arguments
+        .torrents
+        .iter()
+        .filter(|torrent| torrent.error().is_some_and(|x| x != 0))
+        .filter_map(|torrent| torrent.hash().map(ToString::to_string))
+        .collect();
+
+    let count = ids.len();
+
+    // no need to make a request if nothing has errored
+    if count > 0 {
+        torrent_action(&state, &session, TorrentAction::Verify, ids).await?;
+    }
+
+    Ok(count.to_string())
+}
+
+async fn bulk_action_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<(), Response> {
+    // the form can contain the same key multiple times, so we can't deserialize it into a struct
+    let mut action = None;
+    let mut ids = Vec::new();
+    for (key, value) in form {
+        match key.as_str() {
+            "action" => action = Some(value),
+            "ids" => ids.push(value),
+            _ => {}
+        }
+    }
+
+    let action = match action.as_deref() {
+        Some("start") => TorrentAction::Start,
+        Some("pause") => TorrentAction::Pause,
+        Some("verify") => TorrentAction::Verify,
+        Some("remove") => TorrentAction::Remove,
+        _ => return Err(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    if ids.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No torrents are selected").into_response());
+    }
+
+    torrent_action(&state, &session, action, ids)
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+/// The magnet links of the selected torrents, one per line, so that the page can copy them.
+async fn bulk_magnets_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, Response> {
+    // the form can contain the same key multiple times, so we can't deserialize it into a struct
+    let ids: Vec<String> = form
+        .into_iter()
+        .filter(|(key, _value)| key == "ids")
+        .map(|(_key, value)| value)
+        .collect();
+
+    if ids.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No torrents are selected").into_response());
+    }
+
+    let magnets = magnet_links(&state, session.data(), Some(ids))
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // a plain text response, so the links don't need to be escaped
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        magnets.join("\n"),
+    ))
+}
+
+async fn bulk_move_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<(), Response> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    // the form can contain the same key multiple times, so we can't deserialize it into a struct
+    let mut location = None;
+    let mut ids = Vec::new();
+    for (key, value) in form {
+        match key.as_str() {
+            "location" => location = Some(value),
+            "ids" => ids.push(value),
+            _ => {}
+        }
+    }
+
+    let location = validate_location(location.as_deref().unwrap_or_default())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
+
+    if ids.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No torrents are selected").into_response());
+    }
+
+    // transmission accepts a list of ids, so all torrents are moved with a single request
+    let request = transmission::types::Request::torrent_set_location(
+        Some(ids),
+        location.to_string(),
+        /* move_data= */ true,
+    );
+    let _resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+/// Check that the location is an absolute path, which transmission requires. The transmission
+/// daemon may be running on a different OS, so both unix and windows paths are accepted.
+fn validate_location(location: &str) -> Result<&str, &'static str> {
+    let location = location.trim();
+
+    if location.is_empty() {
+        return Err("No location was provided");
+    }
+
+    if location.chars().any(char::is_control) {
+        return Err("The location contains invalid characters");
+    }
+
+    let is_windows_absolute =
+        |x: &[u8]| matches!(x, [drive, b':', b'\\' | b'/', ..] if drive.is_ascii_alphabetic());
+    if !location.starts_with('/') && !is_windows_absolute(location.as_bytes()) {
+        return Err("The location must be an absolute path");
+    }
+
+    Ok(location)
+}
+
+/// An action that can be applied to one or more torrents.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TorrentAction {
+    Start,
+    Pause,
+    Verify,
+    /// Remove the torrent, but keep the downloaded data.
+    Remove,
+}
+
+async fn torrent_action(
+    state: &AppState,
+    session: &session::Session<transmission::rpc::TransmissionRpc>,
+    action: TorrentAction,
+    ids: Vec<String>,
+) -> Result<(), transmission::rpc::RpcError> {
+    #[derive(Deserialize)]
+    struct Empty {}
+
+    let ids = Some(ids);
+    let request = match action {
+        TorrentAction::Start => transmission::types::Request::torrent_start(ids),
+        TorrentAction::Pause => transmission::types::Request::torrent_stop(ids),
+        TorrentAction::Verify => transmission::types::Request::torrent_verify(ids),
+        TorrentAction::Remove => transmission::types::Request::torrent_remove(ids, false),
+    };
+    let _torrent_resp = session
+        .data()
+        .request::<Empty>(&state.http_client, &request)
+        .await?;
+
+    session.data().notify.notify_waiters();
+
+    Ok(())
+}
+
+async fn add_torrent_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Template)]
+    #[template(path = "add-torrent.html")]
+    struct AddTorrentTemplate {
+        display_name: String,
+        default_download_dir: String,
+        download_dirs: Vec<String>,
+    }
+
+    let request = transmission::types::Request::session_get(vec![
+        transmission::types::SessionGetKey::DownloadDir,
+    ]);
+    let session_resp = session
+        .data()
+        .request::<transmission::types::SessionGetResponse>(&state.http_client, &request)
+        .await?;
+
+    let default_download_dir = session_resp
+        .arguments
+        .0
+        .get(&transmission::types::SessionGetKey::DownloadDir)
+        .and_then(|x| x.as_str())
+        .unwrap_or("")
+        .to_string();
+
+    // suggest the directories that existing torrents were downloaded to
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![transmission::types::TorrentGetKey::DownloadDir],
+        None,
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
+
+    let download_dirs = torrent_resp
+        .arguments
+        .download_dirs()
+        .into_iter()
+        .map(ToString::to_string)
+        .collect();
+
+    Ok(AddTorrentTemplate {
+        display_name: state.config.connection.display_name.clone(),
+        default_download_dir,
+        download_dirs,
+    })
+}
+
+/// Check that none of the magnet links are longer than `max_len` bytes. The length is limited to
+/// bound the size of the RPC request, so it's measured in bytes rather than characters.
+fn check_magnet_lengths(magnets: &[&str], max_len: usize) -> Result<(), String> {
+    if magnets.iter().any(|x| x.len() > max_len) {
+        return Err(format!("Magnet links must be at most {max_len} bytes long"));
+    }
+
+    Ok(())
+}
+
+async fn add_torrent_post(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(AddTorrentQuery {
+        magnet,
+        paused,
+        download_dir,
+        cookies,
+    }): Form<AddTorrentQuery>,
+) -> Result<impl IntoResponse, Response> {
+    // the user can add several magnet links or torrent URLs at once, one per line
+    let magnets: Vec<&str> = magnet
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    if magnets.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No magnet links were provided").into_response());
+    }
+
+    // reject the whole request before adding anything, since such a long link is almost certainly
+    // a mistake
+    let max_len = state.config.security.max_magnet_length;
+    check_magnet_lengths(&magnets, max_len)
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg).into_response())?;
+
+    let paused = match paused.as_deref() {
+        Some("on") => true,
+        Some(_) => return Err(StatusCode::BAD_REQUEST.into_response()),
+        None => false,
+    };
+
+    // an empty download dir means that transmission's default should be used
+    let download_dir = download_dir.filter(|x| !x.is_empty());
+
+    let cookies = cookies
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty());
+    if cookies
+        .as_deref()
+        .is_some_and(|x| !is_valid_cookie_header(x))
+    {
+        let msg = "Cookies must be in the format \"name=value; name2=value2\"";
+        return Err((StatusCode::BAD_REQUEST, msg).into_response());
+    }
+
+    let mut results = Vec::new();
+
+    for magnet in &magnets {
+        // cookies are only used when transmission downloads the torrent file from a URL
+        let (submission_key, cookies) = if let Some(info_hash) = magnet_info_hash(magnet) {
+            (format!("add-torrent-{info_hash}"), None)
+        } else if is_torrent_url(magnet) {
+            (format!("add-torrent-{magnet}"), cookies.clone())
+        } else {
+            println!(r#"Incorrect format for magnet link or URL "{magnet}""#);
+            results.push(Err(AddTorrentError::InvalidMagnet));
+            continue;
+        };
+
+        // ignore the magnet if it was just added, for example if the user clicked the button
+        // twice or the browser retried the request
+        const RESUBMIT_WINDOW: Duration = Duration::from_secs(10);
+        if !session.record_submission(&submission_key, RESUBMIT_WINDOW) {
+            results.push(Err(AddTorrentError::RecentlySubmitted));
+            continue;
+        }
+
+        let request = transmission::types::Request::torrent_add(
+            transmission::types::TorrentAddRequired::Filename(magnet.to_string()),
+            /* paused= */ paused,
+            download_dir.clone(),
+            cookies,
+        );
+
+        let resp = session
+            .data()
+            .request::<transmission::types::TorrentAddResponse>(&state.http_client, &request)
+            .await
+            .map(|x| x.arguments)
+            .map_err(AddTorrentError::Rpc);
+
+        if resp.is_err() {
+            session.forget_submission(&submission_key);
+        }
+
+        results.push(resp);
+    }
+
+    session.data().notify.notify_waiters();
+
+    // if only one torrent was added, go to its page
+    if let [result] = results.as_slice() {
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(AddTorrentError::InvalidMagnet) => {
+                return Err((StatusCode::BAD_REQUEST, "Invalid magnet link or URL").into_response())
+            }
+            Err(AddTorrentError::RecentlySubmitted) => {
+                // we don't know the torrent's hash yet if the original request is still in
+                // progress, so go to the torrent list instead
+                session.set_flash("This magnet link was already submitted".to_string());
+                return Ok((
+                    StatusCode::SEE_OTHER,
+                    [(header::LOCATION, "/".to_string())],
+                    "Success",
+                ));
+            }
+            Err(AddTorrentError::Rpc(e)) => return Err(e.clone().into_response()),
+        };
+
+        // make sure we're not injecting weird content into the header
+        let hash = resp.hash_string();
+        assert!(hash.chars().all(char::is_alphanumeric));
+
+        let location = format!("/torrent/{hash}");
+
+        return Ok((
+            StatusCode::SEE_OTHER,
+            [(header::LOCATION, location)],
+            "Success",
+        ));
+    }
+
+    if results
+        .iter()
+        .all(|x| matches!(x, Err(AddTorrentError::InvalidMagnet)))
+    {
+        let msg = "None of the magnet links or URLs were valid";
+        return Err((StatusCode::BAD_REQUEST, msg).into_response());
+    }
+
+    // otherwise show a summary on the torrent list
+    let mut summary = Vec::new();
+    for (magnet, result) in magnets.iter().zip(&results) {
+        summary.push(match result {
+            Ok(resp) if resp.is_duplicate() => format!("Already added: {}", resp.name()),
+            Ok(resp) => format!("Added: {}", resp.name()),
+            Err(AddTorrentError::InvalidMagnet) => format!("Invalid magnet link or URL: {magnet}"),
+            Err(AddTorrentError::RecentlySubmitted) => format!("Already submitted: {magnet}"),
+            Err(AddTorrentError::Rpc(e)) => format!("Failed to add {magnet}: {e}"),
+        });
+    }
+    session.set_flash(summary.join("\n"));
+
+    Ok((
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, "/".to_string())],
+        "Success",
+    ))
+}
+
+/// An error when adding a single torrent.
+enum AddTorrentError {
+    /// Neither a valid magnet link nor a torrent URL.
+    InvalidMagnet,
+    /// The same torrent was submitted very recently.
+    RecentlySubmitted,
+    Rpc(transmission::rpc::RpcError),
+}
+
+/// Get the info hash from a magnet link, or `None` if it isn't a valid magnet link. The info hash
+/// may be hex or base32 encoded, and is returned in lowercase.
+fn magnet_info_hash(magnet: &str) -> Option<String> {
+    let hash = magnet.strip_prefix("magnet:?xt=urn:btih:")?;
+    let hash = hash.split('&').next().unwrap_or_default();
+
+    if hash.is_empty() || !hash.chars().all(|x| x.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    Some(hash.to_ascii_lowercase())
+}
+
+/// Whether the link is an http(s) URL that transmission can download a torrent file from.
+fn is_torrent_url(link: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(link) else {
+        return false;
+    };
+
+    matches!(url.scheme(), "http" | "https") && url.has_host()
+}
+
+/// Whether the string is a reasonable `Cookie` header value: one or more `name=value` pairs
+/// separated by semicolons, without any characters that could break the header.
+fn is_valid_cookie_header(cookies: &str) -> bool {
+    // large enough for any reasonable tracker cookies
+    const MAX_LEN: usize = 4096;
+
+    if cookies.len() > MAX_LEN {
+        return false;
+    }
+
+    cookies.split(';').all(|pair| {
+        let Some((name, value)) = pair.trim().split_once('=') else {
+            return false;
+        };
+
+        let is_token_char = |x: char| x.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(x);
+        let is_value_char = |x: char| x.is_ascii_graphic() && !"\",;\\".contains(x);
+
+        !name.is_empty() && name.chars().all(is_token_char) && value.chars().all(is_value_char)
+    })
+}
+
+async fn stub_torrents_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    headers: header::HeaderMap,
+    Query(query): Query<TorrentListQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let query = query.with_view_from_cookie(&headers);
+    let mut torrents = torrent_list(&state, session.data(), &query).await?;
+    torrents.unread_completions = session.unread_completions();
+    torrents.pin_favorites(session.favorites());
+
+    Ok(TorrentListStubTemplate {
+        query,
+        event_prefix: state.config.performance.sse_event_prefix.clone(),
+        partial: torrents,
+    })
+}
+
+async fn stub_torrent_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(TorrentQuery { hash }): Form<TorrentQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let show_download_dir = state.config.security.show_download_dir;
+    let torrent =
+        torrent_details(session.data(), &state.http_client, &hash, show_download_dir).await?;
+
+    let Some(torrent) = torrent else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    Ok(TorrentStubTemplate {
+        hash,
+        event_prefix: state.config.performance.sse_event_prefix.clone(),
+        partial: torrent,
+    })
+}
+
+/// Only the torrent's status, for clients that poll for state changes and don't need the full
+/// details.
+async fn stub_torrent_status_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Form(TorrentQuery { hash }): Form<TorrentQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        TorrentStatusPartialTemplate::KEYS.to_vec(),
+        Some(vec![hash]),
+    );
+    let mut torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
+
+    if torrent_resp.arguments.torrents.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(TorrentStatusPartialTemplate {
+        details: torrent_resp.arguments.torrents.swap_remove(0),
+    })
+}
+
+async fn sse_torrents_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    headers: header::HeaderMap,
+    Query(query): Query<TorrentListQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    // the stub includes the view in the sse url, but fall back to the cookie just in case
+    let query = query.with_view_from_cookie(&headers);
+    let guard = session
+        .try_start_stream(state.config.performance.max_sse_per_session)
+        .ok_or(StatusCode::TOO_MANY_REQUESTS)?;
+    let event_prefix = state.config.performance.sse_event_prefix.clone();
+
+    let interval = Duration::from_millis(state.config.performance.poll_interval_ms);
+
+    // the state is `None` once the stream should end, and `errored` is set if transmission couldn't
+    // be reached or failed to respond the last time it was polled
+    let stream = futures_util::stream::unfold(
+        Some((
+            session,
+            state,
+            query,
+            None,
+            (TorrentCache::default(), StaticFieldCache::default()),
+            interval,
+            false,
+        )),
+        |stream_state| async move {
+            let (session, state, query, last, caches, mut interval, errored) = stream_state?;
+            let (mut cache, mut static_fields) = caches;
+            let prefix = state.config.performance.sse_event_prefix.clone();
+            let (event, sent) = loop {
+                let jittered = state.config.performance.jittered_poll_interval(interval);
+                let notified = tokio::time::timeout(jittered, session.data().notify.notified())
+                    .await
+                    .is_ok();
+
+                if notified {
+                    let delay = state.config.performance.notification_delay(interval);
+                    tokio::time::sleep(delay).await;
+                }
+
+                if session.expired() {
+                    return None;
+                }
+
+                let start = Instant::now();
+
+                let torrents = if state.config.performance.poll_recently_active {
+                    // something was changed through transportal, so make sure we see it
+                    let force_full_fetch = notified;
+                    cache
+                        .torrent_list(&state, session.data(), &query, force_full_fetch)
+                        .await
+                } else if state.config.performance.sse_prune_fields {
+                    let force_full_fetch = notified;
+                    let torrents = static_fields
+                        .torrent_list(&state, session.data(), &query, force_full_fetch)
+                        .await;
+                    torrents.map(|x| (x, None))
+                } else {
+                    let torrents = torrent_list(&state, session.data(), &query).await;
+                    torrents.map(|x| (x, None))
+                };
+
+                // poll less often if transmission is slow to respond, including if it timed out
+                let new_interval = state
+                    .config
+                    .performance
+                    .adaptive_poll_interval(start.elapsed());
+                let min_interval = Duration::from_millis(state.config.performance.poll_interval_ms);
+                if interval == min_interval && new_interval > min_interval {
+                    println!("Transmission is responding slowly, polling every {new_interval:?}");
+                } else if interval > min_interval && new_interval == min_interval {
+                    println!(
+                        "Transmission is responding quickly again, polling every {new_interval:?}"
+                    );
+                }
+                interval = new_interval;
+
+                let (mut torrents, changed) = match torrents {
+                    Ok(x) => x,
+                    Err(StatusCode::UNAUTHORIZED) => {
+                        // the stream ends after this event since the session is now expired
+                        session.expire();
+                        let caches = (cache, static_fields);
+                        let state = (session, state, query, last, caches, interval, false);
+                        return Some((reauth_event(&prefix), Some(state)));
+                    }
+                    Err(
+                        e @ (StatusCode::GATEWAY_TIMEOUT
+                        | StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE),
+                    ) => {
+                        // the error may be temporary (for example if transmission is slow or
+                        // restarting), so keep polling but only tell the client the first time
+                        println!("Failed to get the torrent list: {e}");
+                        if errored {
+                            continue;
+                        }
+                        let event = if e == StatusCode::GATEWAY_TIMEOUT {
+                            unavailable_event(&prefix)
+                        } else {
+                            error_event(
+                                &prefix,
+                                "Failed to get the torrent list from Transmission. Retrying.",
+                            )
+                        }
+                        .retry(interval);
+                        let caches = (cache, static_fields);
+                        let state = (session, state, query, last, caches, interval, true);
+                        return Some((event, Some(state)));
+                    }
+                    Err(_) => return None,
+                };
+
+                if errored {
+                    // remove the banner, and the list is sent on the next poll if it changed
+                    let event = sse_event(&prefix, "unavailable").data("");
+                    let caches = (cache, static_fields);
+                    let state = (session, state, query, last, caches, interval, false);
+                    return Some((event, Some(state)));
+                }
+
+                torrents.unread_completions = session.unread_completions();
+                torrents.pin_favorites(session.favorites());
+
+                let diff_mode = state.config.performance.sse_diff_mode;
+                let event = torrent_list_event(
+                    &torrents,
+                    diff_mode,
+                    last.as_ref(),
+                    changed.as_ref(),
+                    &prefix,
+                );
+                let event = match event {
+                    Ok(x) => x,
+                    Err(e) => {
+                        println!("Failed to render the torrent list: {e}");
+                        return Some((
+                            error_event(&prefix, "Failed to show the torrent list."),
+                            None,
+                        ));
+                    }
+                };
+                if let Some(x) = event {
+                    break x;
+                }
+            };
+
+            let caches = (cache, static_fields);
+            let state = (session, state, query, Some(sent), caches, interval, false);
+            Some((event, Some(state)))
+        },
+    )
+    .map(move |event| {
+        // the stream owns the guard so that the stream is unregistered when the connection closes
+        let _guard = &guard;
+        Ok(event)
+    });
+
+    Ok(Sse::new(stream).keep_alive(sse_keep_alive(&event_prefix)))
+}
+
+/// An SSE event with the configured prefix prepended to its name, so that the names don't collide
+/// with other event sources if transportal is embedded in a larger page. The templates must use
+/// the same prefix.
+fn sse_event(prefix: &str, name: &str) -> Event {
+    Event::default().event(format!("{prefix}{name}"))
+}
+
+/// The keep-alive for SSE streams. A "heartbeat" event (with empty data) is sent whenever no other
+/// event has been sent for `SSE_HEARTBEAT_INTERVAL`, so clients can assume that the connection has
+/// been lost if they don't receive any events for longer than this.
+fn sse_keep_alive(prefix: &str) -> KeepAlive {
+    // the data field is needed since browsers don't dispatch events that have no data
+    KeepAlive::new()
+        .interval(SSE_HEARTBEAT_INTERVAL)
+        .event(sse_event(prefix, "heartbeat").data(""))
+}
+
+/// The event sent on SSE streams when transmission stops accepting the session's credentials, for
+/// example if transmission's password was changed. The session is expired, so the user needs to
+/// log in again.
+fn reauth_event(prefix: &str) -> Event {
+    let html = r#"<div class="flash main-section">Transmission no longer accepts your username and password. <a href="/login">Log in again</a></div>"#;
+    sse_event(prefix, "reauth").data(html)
+}
+
+/// The event sent on SSE streams when transmission can't be reached. The stream keeps polling, and
+/// the banner is replaced once transmission responds again.
+fn unavailable_event(prefix: &str) -> Event {
+    let html = r#"<div class="flash main-section">Transmission is unavailable. Retrying…</div>"#;
+    sse_event(prefix, "unavailable").data(html)
+}
+
+/// An event that tells the client about a server error, which may or may not end the stream. The
+/// templates don't swap it in since browsers also dispatch "error" events (with no data) on the
+/// `EventSource` when the connection is lost.
+fn error_event(prefix: &str, msg: &str) -> Event {
+    sse_event(prefix, "error").data(msg)
+}
+
+/// Should be kept in sync with the timeout in "index.html".
+const SSE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The torrent list that was last sent to the client.
+enum SentTorrentList {
+    /// The entire rendered list.
+    Full(String),
+    /// The hash and rendered html of each torrent.
+    Rows(Vec<(String, String)>),
+}
+
+/// Build the event that updates the client's torrent list, given the torrent list that was last
+/// sent to the client. Returns `None` if nothing has changed. If `changes` is given, only those
+/// torrents can have changed since the last list was sent.
+fn torrent_list_event(
+    torrents: &TorrentListPartialTemplate,
+    diff_mode: config::SseDiffMode,
+    last: Option<&SentTorrentList>,
+    changes: Option<&TorrentListChanges>,
+    prefix: &str,
+) -> askama::Result<Option<(Event, SentTorrentList)>> {
+    let list_event = |html| sse_event(prefix, "list").data(html);
+
+    match (diff_mode, last) {
+        (config::SseDiffMode::Full, last) => {
+            let html = torrents.render()?;
+
+            if let Some(SentTorrentList::Full(last)) = last {
+                if html == *last {
+                    return Ok(None);
+                }
+            }
+
+            Ok(Some((
+                list_event(html.clone()),
+                SentTorrentList::Full(html),
+            )))
+        }
+        (config::SseDiffMode::Rows, Some(SentTorrentList::Rows(last))) => {
+            // there's no need to render the torrents that haven't changed again
+            let reuse = match changes {
+                Some(changes) => last
+                    .iter()
+                    .filter(|(hash, _html)| !changes.updated.contains(hash))
+                    .map(|(hash, html)| (hash.as_str(), html.as_str()))
+                    .collect(),
+                None => HashMap::new(),
+            };
+            let rows = torrents.render_rows(&reuse)?;
+
+            // torrents that we know were removed can be removed from the client's list directly
+            let is_removed = |hash: &String| changes.is_some_and(|x| x.removed.contains(hash));
+            let (removed, kept): (Vec<_>, Vec<_>) = last.iter().partition(|x| is_removed(&x.0));
+
+            // if any other torrents were added, removed, or reordered, replace the entire list (also
+            // if the last torrent was removed, so that the empty state is shown)
+            let now_empty = rows.is_empty() && !removed.is_empty();
+            if now_empty || !rows.iter().map(|x| &x.0).eq(kept.iter().map(|x| &x.0)) {
+                let event = list_event(torrents.render()?);
+                return Ok(Some((event, SentTorrentList::Rows(rows))));
+            }
+
+            // otherwise only send the torrents that changed
+            let mut changed: String = rows
+                .iter()
+                .zip(kept)
+                .filter(|(new, old)| new.1 != old.1)
+                .map(|(new, _old)| new.1.as_str())
+                .collect();
+
+            for (hash, _html) in removed {
+                // the hash is a hex string from transmission, so doesn't need to be escaped
+                changed.push_str(&format!(
+                    r#"<li id="torrent-{hash}" hx-swap-oob="delete"></li>"#
+                ));
+            }
+
+            if changed.is_empty() {
+                return Ok(None);
+            }
+
+            // the count also has the number of unread completions, which may have changed
+            let count = TorrentCountPartialTemplate {
+                count: rows.len(),
+                unread_completions: torrents.unread_completions,
+                oob: true,
+            };
+            changed.push_str(&count.render()?);
+
+            let event = sse_event(prefix, "rows").data(changed);
+            Ok(Some((event, SentTorrentList::Rows(rows))))
+        }
+        (config::SseDiffMode::Rows, _) => {
+            let event = list_event(torrents.render()?);
+            Ok(Some((
+                event,
+                SentTorrentList::Rows(torrents.render_rows(&HashMap::new())?),
+            )))
+        }
+    }
+}
+
+async fn sse_torrent_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Query(query): Query<TorrentQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let guard = session
+        .try_start_stream(state.config.performance.max_sse_per_session)
+        .ok_or(StatusCode::TOO_MANY_REQUESTS)?;
+    let event_prefix = state.config.performance.sse_event_prefix.clone();
+
+    // the state is `None` once the stream should end, and `errored` is set if the last request to
+    // transmission failed
+    let stream = futures_util::stream::unfold(
+        Some((session, state, query, None, false)),
+        |stream_state| async move {
+            let (session, state, query, last, errored) = stream_state?;
+            let prefix = state.config.performance.sse_event_prefix.clone();
+            let html = loop {
+                let interval = Duration::from_millis(state.config.performance.poll_interval_ms);
+                let jittered = state.config.performance.jittered_poll_interval(interval);
+                let notified = tokio::time::timeout(jittered, session.data().notify.notified())
+                    .await
+                    .is_ok();
+
+                if notified {
+                    let delay = state.config.performance.notification_delay(interval);
+                    tokio::time::sleep(delay).await;
+                }
+
+                if session.expired() {
+                    return None;
+                }
+
+                let torrent = torrent_details(
+                    session.data(),
+                    &state.http_client,
+                    &query.hash,
+                    state.config.security.show_download_dir,
+                )
+                .await;
+
+                let torrent = match torrent {
+                    Ok(x) => x,
+                    Err(StatusCode::UNAUTHORIZED) => {
+                        // the stream ends after this event since the session is now expired
+                        session.expire();
+                        let state = (session, state, query, last, errored);
+                        return Some((reauth_event(&prefix), Some(state)));
+                    }
+                    Err(e) => {
+                        // the error may be temporary (for example if transmission is restarting),
+                        // so keep polling but only tell the client the first time
+                        println!("Failed to get the torrent details: {e}");
+                        if errored {
+                            continue;
+                        }
+                        let event = if e == StatusCode::GATEWAY_TIMEOUT {
+                            // replaces the details until they're sent again
+                            unavailable_event(&prefix)
+                        } else {
+                            error_event(
+                                &prefix,
+                                "Failed to get the torrent details from Transmission. Retrying.",
+                            )
+                        }
+                        .retry(interval);
+                        // send the details again once transmission responds
+                        return Some((event, Some((session, state, query, None, true))));
+                    }
+                };
+
+                let Some(torrent) = torrent else {
+                    return Some((
+                        sse_event(&prefix, "removed").data("<b>Removed</b>"),
+                        Some((session, state, query, None, false)),
+                    ));
+                };
+
+                let html = match torrent.render() {
+                    Ok(x) => x,
+                    Err(e) => {
+                        println!("Failed to render the torrent details: {e}");
+                        return Some((
+                            error_event(&prefix, "Failed to show the torrent details."),
+                            None,
+                        ));
+                    }
+                };
+
+                if let Some(ref last) = last {
+                    if html != *last {
+                        break html;
+                    }
+                } else {
+                    break html;
+                }
+            };
+
+            let event = sse_event(&prefix, "details").data(html.clone());
+            Some((event, Some((session, state, query, Some(html), false))))
+        },
+    )
+    .map(move |event| {
+        // the stream owns the guard so that the stream is unregistered when the connection closes
+        let _guard = &guard;
+        Ok(event)
+    });
+
+    Ok(Sse::new(stream).keep_alive(sse_keep_alive(&event_prefix)))
+}
+
+/// Create an API token for the current session, which scripts can use in an `Authorization:
+/// Bearer` header to access the api routes. Requires the session cookie.
+async fn api_token_post(
+    State(state): State<Arc<AppState>>,
+    headers: header::HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Serialize)]
+    struct TokenResponse {
+        token: String,
+    }
+
+    let (session_secret, _session) = session_cookie_from_headers(&state, &headers)?;
+    let token = state
+        .sessions
+        .new_token(session_secret)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    Ok(Json(TokenResponse {
+        token: token.to_string(),
+    }))
+}
+
+async fn api_session_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Serialize)]
+    struct SessionResponse {
+        /// When the session expires, in seconds since the unix epoch.
+        expires: u64,
+        /// The number of seconds until the session expires.
+        expires_in_secs: u64,
+        transmission: transmission::types::TransmissionVersion,
+    }
+
+    let expires = session.expires();
+    let expires_in_secs = expires
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+        .as_secs();
+    let expires = expires
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    // the version is cached, so this only makes a request the first time
+    let transmission = session.data().version(&state.http_client).await?.clone();
+
+    Ok(Json(SessionResponse {
+        expires,
+        expires_in_secs,
+        transmission,
+    }))
+}
+
+/// The number of torrents with each status, for showing a badge in a browser extension or the
+/// page title. Only the status and error of each torrent are requested, so this is cheap enough to
+/// poll, and identical requests made at the same time share a single transmission request.
+async fn api_counts_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Debug, Default, Serialize)]
+    struct CountsResponse {
+        total: u64,
+        /// Downloading or queued to download.
+        downloading: u64,
+        /// Seeding or queued to seed.
+        seeding: u64,
+        paused: u64,
+        /// Torrents with an error, regardless of their status.
+        errored: u64,
+        /// Torrents that completed since the user last viewed the torrent list.
+        unread_completions: usize,
+    }
+
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        vec![
+            transmission::types::TorrentGetKey::Status,
+            transmission::types::TorrentGetKey::Error,
+        ],
+        None,
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
+
+    let mut counts = CountsResponse {
+        unread_completions: session.unread_completions(),
+        ..Default::default()
+    };
+
+    for torrent in &torrent_resp.arguments.torrents {
+        use transmission::types::TorrentStatus;
+
+        counts.total += 1;
+
+        match torrent.status() {
+            Some(TorrentStatus::Downloading | TorrentStatus::DownloadQueued) => {
+                counts.downloading += 1
+            }
+            Some(TorrentStatus::Seeding | TorrentStatus::SeedQueued) => counts.seeding += 1,
+            Some(TorrentStatus::Stopped) => counts.paused += 1,
+            _ => {}
+        }
+
+        if torrent.error().is_some_and(|x| x != 0) {
+            counts.errored += 1;
+        }
+    }
+
+    Ok(Json(counts))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiTorrentsQuery {
+    /// Comma-separated torrent hashes. All torrents are returned if not set.
+    ids: Option<String>,
+}
+
+impl ApiTorrentsQuery {
+    /// The most torrents that can be requested by hash at once.
+    const MAX_IDS: usize = 1000;
+
+    /// The requested hashes, or `None` if all torrents were requested. Returns an error if any of
+    /// the hashes isn't an info hash, or if there are too many.
+    fn ids(&self) -> Result<Option<Vec<String>>, StatusCode> {
+        let Some(ids) = &self.ids else {
+            return Ok(None);
+        };
+
+        // an empty list would mean all torrents to transmission, which isn't what was asked for
+        let ids: Vec<String> = ids.split(',').map(|x| x.trim().to_string()).collect();
+        if ids.len() > Self::MAX_IDS || !ids.iter().all(|x| is_info_hash(x)) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        Ok(Some(ids))
+    }
+}
+
+/// The torrents (or only the torrents with the given hashes), with the fields used by the torrent
+/// list. Hashes are passed to transmission, so only the requested torrents are fetched.
+async fn api_torrents_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Query(query): Query<ApiTorrentsQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Serialize)]
+    struct TorrentsResponse {
+        torrents: Vec<transmission::types::Torrent>,
+    }
+
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        TorrentListView::default().keys(state.config.performance.list_fields.as_deref()),
+        query.ids()?,
+    );
+    let torrent_resp = session
+        .data()
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+        .await?;
+
+    Ok(Json(TorrentsResponse {
+        torrents: torrent_resp.arguments.torrents,
+    }))
+}
+
+/// The magnet links of all torrents (or only the torrents with the given hashes) as a JSON array.
+async fn api_magnets_get(
+    State(state): State<Arc<AppState>>,
+    SessionArc(session): SessionArc,
+    Query(query): Query<ApiTorrentsQuery>,
+) -> Result<impl IntoResponse, Response> {
+    let ids = query.ids().map_err(IntoResponse::into_response)?;
+    let magnets = magnet_links(&state, session.data(), ids)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    Ok(Json(magnets))
+}
+
+async fn api_health_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    #[derive(Serialize)]
+    struct HealthResponse {
+        /// Whether transmission is running, even if it requires credentials.
+        transmission_reachable: bool,
+        message: String,
+        /// The number of requests to transmission that are currently in-flight.
+        rpc_in_flight: usize,
+        /// The number of open connections, if the server is bound to a unix socket.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        unix_connections: Option<usize>,
+    }
+
+    let url = &state.config.connection.rpc_url;
+    let diagnosis = transmission::rpc::Diagnosis::check(&state.http_client, url).await;
+    state.record_diagnosis(&diagnosis);
+
+    let status = if diagnosis.is_reachable() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(HealthResponse {
+            transmission_reachable: diagnosis.is_reachable(),
+            message: diagnosis.guidance(),
+            rpc_in_flight: state.rpc_limiter.in_flight(),
+            unix_connections: unix_connections(&state),
+        }),
+    )
+}
+
+/// The number of open connections to the unix socket, or `None` if the server isn't bound to a
+/// unix socket.
+fn unix_connections(state: &AppState) -> Option<usize> {
+    match state.config.connection.bind_address {
+        config::CompatSocketAddr::Ip(_) => None,
+        #[cfg(target_os = "linux")]
+        config::CompatSocketAddr::Unix(_) => Some(state.unix_connections.open_connections()),
+        #[cfg(not(target_os = "linux"))]
+        config::CompatSocketAddr::Unix(_) => None,
+    }
+}
+
+async fn api_version_get(
+    State(state): State<Arc<AppState>>,
+    // the transmission version is only available to logged in users
+    session: Option<SessionArc>,
+) -> Result<impl IntoResponse, StatusCode> {
+    #[derive(Serialize)]
+    struct VersionResponse {
+        transportal: &'static str,
+        transmission: Option<transmission::types::TransmissionVersion>,
+        /// Optional features supported by the transmission server.
+        features: Option<Vec<transmission::types::RpcFeature>>,
+    }
+
+    let transmission = match session {
+        Some(SessionArc(session)) => {
+            Some(session.data().version(&state.http_client).await?.clone())
+        }
+        None => None,
+    };
+
+    let features = transmission.as_ref().map(|version| {
+        transmission::types::RpcFeature::ALL
+            .into_iter()
+            .filter(|x| version.supports(*x))
+            .collect()
+    });
+
+    Ok(Json(VersionResponse {
+        transportal: env!("CARGO_PKG_VERSION"),
+        transmission,
+        features,
+    }))
+}
+
+async fn torrent_list(
+    state: &AppState,
+    rpc: &transmission::rpc::TransmissionRpc,
+    query: &TorrentListQuery,
+) -> Result<TorrentListPartialTemplate, StatusCode> {
+    let view = query.view.unwrap_or_default();
+
+    let request = transmission::types::Request::torrent_get(
+        transmission::types::TorrentGetFormat::Objects,
+        view.keys(state.config.performance.list_fields.as_deref()),
         None,
     );
-    let mut torrent_resp = rpc
-        .request::<transmission::types::TorrentGetResponse>(client, &request)
+    let torrent_resp = rpc
+        .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
         .await?;
 
-    if let Some(filter) = filter {
-        torrent_resp.arguments.torrents.retain(|torrent| {
-            torrent
-                .get(&transmission::types::TorrentGetKey::Name)
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_lowercase()
-                .contains(&filter.to_lowercase())
-        });
+    let units = rpc.units(&state.http_client).await?.clone();
+
+    Ok(build_torrent_list(
+        state,
+        torrent_resp.arguments.torrents,
+        query,
+        units,
+        true,
+    ))
+}
+
+/// Filter and sort the torrents for the torrent list. `torrents` must contain all of
+/// transmission's torrents, and `fetched_in_full` is set if none of them came from a cache.
+fn build_torrent_list(
+    state: &AppState,
+    mut torrents: Vec<transmission::types::Torrent>,
+    query: &TorrentListQuery,
+    units: transmission::types::Units,
+    fetched_in_full: bool,
+) -> TorrentListPartialTemplate {
+    // the notifier needs to see all torrents, so must be given the list before it's filtered
+    let completed = state
+        .notifier
+        .observe(&state.http_client, &torrents, fetched_in_full);
+    if completed > 0 {
+        state.sessions.add_unread_completions(completed);
+    }
+
+    if let Some(filter) = &query.filter {
+        let filter = filter.to_lowercase();
+        torrents.retain(|torrent| torrent_name_matches(torrent, &filter));
+    }
+
+    match query.show {
+        TorrentListShow::All => {}
+        TorrentListShow::Attention => torrents.retain(|torrent| {
+            !transmission::types::AttentionReason::from_torrent(torrent).is_empty()
+        }),
     }
 
-    torrent_resp.arguments.torrents.sort_by_cached_key(|x| {
-        x.get(&transmission::types::TorrentGetKey::AddedDate)
-            .and_then(|a| a.as_u64())
-            .map(|a| u64::MAX - a)
+    if let Some(statuses) = query.status.statuses() {
+        torrents.retain(|torrent| torrent.status().is_some_and(|x| statuses.contains(&x)));
+    }
+
+    // the index, stub, and SSE lists are all built here, so they always agree on the order
+    sort_torrents(&mut torrents, query.sort_field(), query.ascending());
+
+    TorrentListPartialTemplate {
+        torrents,
+        query: query.clone(),
+        view: query.view.unwrap_or_default(),
+        units,
+        // set by the caller, since they depend on the session
+        unread_completions: 0,
+        favorites: HashSet::new(),
+    }
+}
+
+/// Sort the torrents by the field. Torrents that are missing the field are always listed last.
+/// Ties are broken by the torrent's hash so that the order doesn't change between updates.
+fn sort_torrents(
+    torrents: &mut [transmission::types::Torrent],
+    field: TorrentSortField,
+    ascending: bool,
+) {
+    torrents.sort_by_cached_key(|x| {
+        let key = field.key(x);
+        let hash = x.hash().map(ToString::to_string);
+        (key.is_none(), key, hash)
     });
 
-    Ok(TorrentListPartialTemplate {
-        torrents: torrent_resp.arguments.torrents,
-    })
+    if !ascending {
+        // only reverse the torrents that have the field, so that the others stay at the end
+        let with_key = torrents.iter().filter(|x| field.key(x).is_some()).count();
+        torrents[..with_key].reverse();
+    }
+}
+
+/// Whether the torrent's name contains `filter`, which must be lowercase. Torrents without a name
+/// (which transmission shouldn't return) never match.
+fn torrent_name_matches(torrent: &transmission::types::Torrent, filter: &str) -> bool {
+    torrent
+        .name()
+        .is_some_and(|x| x.to_lowercase().contains(filter))
+}
+
+/// All of transmission's torrents, kept up to date by mostly only requesting the torrents that
+/// were recently active.
+#[derive(Debug, Default)]
+struct TorrentCache {
+    /// Keyed by the torrent's hash.
+    torrents: HashMap<String, transmission::types::Torrent>,
+    /// The hash of each torrent, keyed by transmission's torrent id. Transmission only tells us
+    /// the ids of removed torrents, but ids aren't persistent (they change when transmission
+    /// restarts), so we use hashes everywhere else.
+    hashes: HashMap<u64, String>,
+    last_full_fetch: Option<Instant>,
+}
+
+/// The torrents that changed since the torrent list was last requested.
+#[derive(Debug, Default)]
+struct TorrentListChanges {
+    /// Hashes of torrents that were added or may have changed.
+    updated: HashSet<String>,
+    /// Hashes of torrents that were removed.
+    removed: HashSet<String>,
+}
+
+impl TorrentCache {
+    /// How often all torrents are requested, to pick up changes that don't make a torrent
+    /// recently active (for example if another client removed a torrent).
+    const FULL_FETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Like `torrent_list`, but only requests all torrents from transmission if `force_full_fetch`
+    /// is set or it hasn't done so recently. Also returns the torrents that changed since the last
+    /// call, or `None` if any torrent may have changed.
+    async fn torrent_list(
+        &mut self,
+        state: &AppState,
+        rpc: &transmission::rpc::TransmissionRpc,
+        query: &TorrentListQuery,
+        force_full_fetch: bool,
+    ) -> Result<(TorrentListPartialTemplate, Option<TorrentListChanges>), StatusCode> {
+        let mut keys = query
+            .view
+            .unwrap_or_default()
+            .keys(state.config.performance.list_fields.as_deref());
+        keys.push(transmission::types::TorrentGetKey::Id);
+
+        let full_fetch = force_full_fetch
+            || !matches!(self.last_full_fetch, Some(x) if x.elapsed() < Self::FULL_FETCH_INTERVAL);
+
+        let request = if full_fetch {
+            transmission::types::Request::torrent_get(
+                transmission::types::TorrentGetFormat::Objects,
+                keys,
+                None,
+            )
+        } else {
+            transmission::types::Request::torrent_get_recently_active(
+                transmission::types::TorrentGetFormat::Objects,
+                keys,
+            )
+        };
+        let torrent_resp = rpc
+            .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+            .await?;
+
+        let id_and_hash = |torrent: &transmission::types::Torrent| {
+            Some((torrent.id()?, torrent.hash()?.to_string()))
+        };
+
+        if full_fetch {
+            self.torrents.clear();
+            self.hashes.clear();
+            self.last_full_fetch = Some(Instant::now());
+        }
+
+        let updated: Vec<_> = torrent_resp
+            .arguments
+            .torrents
+            .into_iter()
+            .filter_map(|torrent| Some((id_and_hash(&torrent)?, torrent)))
+            .collect();
+
+        let mut changes = TorrentListChanges {
+            updated: updated
+                .iter()
+                .map(|((_id, hash), _)| hash.clone())
+                .collect(),
+            removed: HashSet::new(),
+        };
+
+        for id in torrent_resp.arguments.removed {
+            let Some(hash) = self.hashes.remove(&id) else {
+                continue;
+            };
+
+            // the torrent may have been removed and added again with a new id
+            if changes.updated.contains(&hash) {
+                continue;
+            }
+
+            self.torrents.remove(&hash);
+            changes.removed.insert(hash);
+        }
+
+        for ((id, hash), torrent) in updated {
+            self.hashes.insert(id, hash.clone());
+            self.torrents.insert(hash, torrent);
+        }
+
+        let changes = (!full_fetch).then_some(changes);
+
+        let units = rpc.units(&state.http_client).await?.clone();
+        let torrents = self.torrents.values().cloned().collect();
+
+        let list = build_torrent_list(state, torrents, query, units, full_fetch);
+        Ok((list, changes))
+    }
+}
+
+/// The fields of each torrent that rarely change, so that the torrent list can be updated by
+/// only requesting the fields that change often (`TorrentGetKey::changes_often`) and merging in
+/// the other fields from the last time all fields were requested. All fields are requested again
+/// if something was changed through transportal, a torrent was added, a torrent is still
+/// downloading its metadata, or it's been `TorrentCache::FULL_FETCH_INTERVAL` since the last time.
+/// Changes made by other clients to fields that rarely change (such as a torrent's labels) can
+/// therefore take up to that long to be shown.
+#[derive(Debug, Default)]
+struct StaticFieldCache {
+    /// Keyed by the torrent's hash.
+    torrents: HashMap<String, transmission::types::Torrent>,
+    /// Whether any torrent didn't have its metadata yet, in which case its name and size will
+    /// change.
+    missing_metadata: bool,
+    last_full_fetch: Option<Instant>,
+}
+
+impl StaticFieldCache {
+    /// Like `torrent_list`, but only requests the fields that change often unless
+    /// `force_full_fetch` is set or all fields are needed.
+    async fn torrent_list(
+        &mut self,
+        state: &AppState,
+        rpc: &transmission::rpc::TransmissionRpc,
+        query: &TorrentListQuery,
+        force_full_fetch: bool,
+    ) -> Result<TorrentListPartialTemplate, StatusCode> {
+        use transmission::types::TorrentGetKey;
+
+        let mut keys = query
+            .view
+            .unwrap_or_default()
+            .keys(state.config.performance.list_fields.as_deref());
+        keys.push(TorrentGetKey::MetadataPercentComplete);
+
+        let full_fetch = force_full_fetch
+            || self.missing_metadata
+            || !matches!(self.last_full_fetch, Some(x) if x.elapsed() < TorrentCache::FULL_FETCH_INTERVAL);
+
+        if !full_fetch {
+            let volatile_keys = keys
+                .iter()
+                .filter(|x| x.changes_often() || **x == TorrentGetKey::HashString)
+                .cloned()
+                .collect();
+            let request = transmission::types::Request::torrent_get(
+                transmission::types::TorrentGetFormat::Objects,
+                volatile_keys,
+                None,
+            );
+            let mut torrents = rpc
+                .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+                .await?
+                .arguments
+                .torrents;
+
+            // if there's a new torrent, we don't know its other fields
+            let merged = torrents.iter_mut().all(|torrent| {
+                let Some(cached) = torrent.hash().and_then(|x| self.torrents.get(x)) else {
+                    return false;
+                };
+                for (key, value) in cached.fields() {
+                    let fields = torrent.fields_mut();
+                    fields.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+                true
+            });
+
+            // every torrent was fetched, even though some of their fields are cached
+            if merged {
+                let units = rpc.units(&state.http_client).await?.clone();
+                return Ok(build_torrent_list(state, torrents, query, units, true));
+            }
+        }
+
+        let request = transmission::types::Request::torrent_get(
+            transmission::types::TorrentGetFormat::Objects,
+            keys,
+            None,
+        );
+        let torrents = rpc
+            .request::<transmission::types::TorrentGetResponse>(&state.http_client, &request)
+            .await?
+            .arguments
+            .torrents;
+
+        self.missing_metadata = torrents
+            .iter()
+            .any(|torrent| torrent.metadata_percent_complete().is_some_and(|x| x < 1.0));
+        self.torrents = torrents
+            .iter()
+            .filter_map(|torrent| {
+                let fields: BTreeMap<_, _> = torrent
+                    .fields()
+                    .iter()
+                    .filter(|(key, _)| !key.changes_often())
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                Some((torrent.hash()?.to_string(), fields.into()))
+            })
+            .collect();
+        self.last_full_fetch = Some(Instant::now());
+
+        let units = rpc.units(&state.http_client).await?.clone();
+        Ok(build_torrent_list(state, torrents, query, units, true))
+    }
 }
 
 async fn torrent_details(
     rpc: &transmission::rpc::TransmissionRpc,
     client: &reqwest::Client,
     hash: &str,
+    show_download_dir: bool,
 ) -> Result<Option<TorrentPartialTemplate>, StatusCode> {
+    let mut keys = TorrentPartialTemplate::KEYS.to_vec();
+
+    // the template only shows the path if the download dir is present
+    if show_download_dir {
+        keys.push(transmission::types::TorrentGetKey::DownloadDir);
+    }
+
     let request = transmission::types::Request::torrent_get(
         transmission::types::TorrentGetFormat::Objects,
-        vec![
-            transmission::types::TorrentGetKey::DateCreated,
-            transmission::types::TorrentGetKey::AddedDate,
-            transmission::types::TorrentGetKey::Id,
-            transmission::types::TorrentGetKey::Name,
-            transmission::types::TorrentGetKey::HashString,
-            transmission::types::TorrentGetKey::PercentComplete,
-            transmission::types::TorrentGetKey::PercentDone,
-            transmission::types::TorrentGetKey::Status,
-        ],
+        keys,
         Some(vec![hash.to_string()]),
     );
     let mut torrent_resp = rpc
@@ -643,38 +3855,903 @@ impl<S: Send + Sync + Deref<Target = AppState>> FromRequestParts<S> for SessionA
     type Rejection = StatusCode;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        Ok(Self(session_from_headers(state, &parts.headers)?))
+        let token = if parts.uri.path().starts_with("/api/") {
+            // scripts can use an api token instead of a cookie, but only for the api routes
+            api_token_from_headers(&parts.headers)?
+        } else {
+            None
+        };
+
+        let session = match token {
+            Some(token) => state
+                .sessions
+                .session_from_token(token)
+                .ok_or(StatusCode::UNAUTHORIZED)?,
+            None => session_from_headers(state, &parts.headers)?,
+        };
+
+        session.touch();
+
+        if let Some(window) = state.config.security.sliding_expiry_secs {
+            session.refresh_expiry(Duration::from_secs(window));
+        }
+
+        Ok(Self(session))
     }
 }
 
+/// The token from an `Authorization: Bearer <token>` header, if the request has one.
+fn api_token_from_headers(
+    headers: &header::HeaderMap,
+) -> Result<Option<session::ApiToken>, StatusCode> {
+    let Some(auth) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+
+    let token = auth
+        .to_str()
+        .ok()
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let token = token.trim().parse().or(Err(StatusCode::UNAUTHORIZED))?;
+
+    Ok(Some(token))
+}
+
 fn session_from_headers(
     state: &AppState,
     headers: &header::HeaderMap,
 ) -> Result<Arc<session::Session<transmission::rpc::TransmissionRpc>>, StatusCode> {
-    let session_secret = session_secret_from_headers(headers)?;
+    session_cookie_from_headers(state, headers).map(|(_secret, session)| session)
+}
 
-    state
-        .sessions
-        .session(session_secret)
+/// The secret and session of the request's session cookie. Browsers may send several
+/// `session_secret` cookies (for example a stale cookie that was set with a different path), so
+/// the first one with a valid session is used.
+fn session_cookie_from_headers(
+    state: &AppState,
+    headers: &header::HeaderMap,
+) -> Result<
+    (
+        session::SessionSecret,
+        Arc<session::Session<transmission::rpc::TransmissionRpc>>,
+    ),
+    StatusCode,
+> {
+    session_secrets_from_headers(headers)?
+        .into_iter()
+        .find_map(|secret| Some((secret, state.sessions.session(secret)?)))
         .ok_or(StatusCode::UNAUTHORIZED)
 }
 
-fn session_secret_from_headers(
+/// All of the session secrets in the request's cookies, in the order that the browser sent them.
+fn session_secrets_from_headers(
     headers: &header::HeaderMap,
-) -> Result<session::SessionSecret, StatusCode> {
+) -> Result<Vec<session::SessionSecret>, StatusCode> {
     let cookies = headers
         .get(header::COOKIE)
         .ok_or(StatusCode::UNAUTHORIZED)?
         .to_str()
         .or(Err(StatusCode::BAD_REQUEST))?;
 
-    let mut cookies = Cookie::split_parse(cookies);
+    let secrets: Vec<_> = Cookie::split_parse(cookies)
+        .filter_map(Result::ok)
+        .filter(|c| c.name() == "session_secret")
+        .map(|c| c.value().parse::<u128>())
+        .collect();
 
-    let session_secret = cookies
-        .find_map(|c| c.ok().filter(|c| c.name() == "session_secret"))
-        .ok_or(StatusCode::UNAUTHORIZED)?;
-    let session_secret = session_secret.value();
-    let session_secret = session_secret.parse().or(Err(StatusCode::BAD_REQUEST))?;
+    if secrets.is_empty() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // only reject the request if none of the cookies are well-formed
+    let secrets: Vec<_> = secrets
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(session::SessionSecret::new)
+        .collect();
+
+    if secrets.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(secrets)
+}
+
+fn cookie_from_headers(headers: &header::HeaderMap, name: &str) -> Option<String> {
+    let cookies = headers.get(header::COOKIE)?.to_str().ok()?;
+
+    Cookie::split_parse(cookies)
+        .find_map(|c| c.ok().filter(|c| c.name() == name))
+        .map(|c| c.value().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `extra` is added to the `[connection]` section, and can start other sections.
+    fn test_state(extra: &str) -> AppState {
+        AppState::new(config::tests::config_with(extra).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_admin_users_auth() {
+        let state = |extra: &str| {
+            let extra = format!("{extra}\n[security]\nadmin_users = [\"admin\"]");
+            AppState::new(config::tests::config_with(&extra).unwrap())
+        };
+
+        assert!(state("").is_ok());
+        assert!(state(r#"rpc_auth = "basic""#).is_ok());
+        assert!(state(r#"rpc_auth = "bearer""#).is_err());
+        assert!(state("rpc_auth = \"header\"\nrpc_auth_header = \"X-Api-Key\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_is_admin() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // a transmission server that responds to every request with `status`
+        let transmission = |status: StatusCode, requests: Arc<AtomicUsize>| async move {
+            let app = Router::new().route(
+                transmission::mock::RPC_PATH,
+                post(move || async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    status
+                }),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            config::RpcUrl::new(
+                format!("http://{addr}"),
+                transmission::mock::RPC_PATH.to_string(),
+            )
+        };
+
+        for (status, expected) in [
+            (StatusCode::UNAUTHORIZED, true),
+            // transmission doesn't require credentials, so didn't check the username
+            (StatusCode::CONFLICT, false),
+        ] {
+            let requests = Arc::new(AtomicUsize::new(0));
+            let mut state = test_state("[security]\nadmin_users = [\"admin\"]");
+            state.config.connection.rpc_url = transmission(status, Arc::clone(&requests)).await;
+
+            let session = |username: &str| {
+                let auth = transmission::rpc::TransmissionAuth {
+                    username: username.to_string(),
+                    password: "pass".to_string(),
+                };
+                let rpc = transmission::rpc::TransmissionRpc::new(
+                    state.config.connection.rpc_url.clone(),
+                    auth,
+                    Default::default(),
+                    None,
+                    Arc::clone(&state.rpc_limiter),
+                );
+                session::Session::new(rpc)
+            };
+
+            assert_eq!(is_admin(&state, &session("admin")).await, expected);
+            assert!(!is_admin(&state, &session("user")).await);
+
+            // transmission is only checked once
+            assert_eq!(is_admin(&state, &session("admin")).await, expected);
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_transmission_non_admin() {
+        use transmission::mock::MockTransmission;
+
+        let mock = MockTransmission::with_arguments(serde_json::json!({})).await;
+        let shutdown = |extra: &str| {
+            let state = Arc::new(test_state(&format!("[security]\n{extra}")));
+            // logs in as "user"
+            let session = Arc::new(session::Session::new(transmission::mock::test_rpc(
+                mock.url(),
+            )));
+            let form = ShutdownForm {
+                confirm: "shutdown".to_string(),
+            };
+            async move {
+                shutdown_transmission_post(State(state), SessionArc(session), Form(form))
+                    .await
+                    .map_err(|e| e.status())
+            }
+        };
+
+        assert_eq!(
+            shutdown(r#"admin_users = ["admin"]"#).await,
+            Err(StatusCode::NOT_FOUND),
+        );
+        assert_eq!(
+            shutdown("allow_transmission_shutdown = true\nadmin_users = [\"admin\"]").await,
+            Err(StatusCode::FORBIDDEN),
+        );
+        assert_eq!(mock.requests(), 0);
+    }
+
+    #[test]
+    fn test_session_cookie_from_headers() {
+        let state = test_state("");
+        let rpc = transmission::mock::test_rpc(state.config.connection.rpc_url.clone());
+        let secret = state.sessions.new_session(session::Session::new(rpc));
+        let value = secret.as_cookie(true, None).to_string();
+        let value = value.split(';').next().unwrap();
+
+        let lookup = |cookie: &str| {
+            let mut headers = header::HeaderMap::new();
+            headers.insert(header::COOKIE, cookie.parse().unwrap());
+            session_cookie_from_headers(&state, &headers).map(|(secret, _session)| secret)
+        };
+
+        assert!(lookup(&format!("session_secret={value}")).unwrap() == secret);
+
+        // a stale or malformed cookie sent alongside the valid one is skipped
+        let stale = format!("session_secret={}", "0".repeat(value.len()));
+        assert!(lookup(&format!("{stale}; session_secret={value}")).unwrap() == secret);
+        assert!(lookup(&format!("session_secret=abc; session_secret={value}")).unwrap() == secret);
+
+        assert_eq!(lookup(&stale), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(lookup("session_secret=abc"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(lookup("view=cards"), Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_torrent_list_status() {
+        let state = test_state("");
+        let torrents: Vec<_> = [0, 3, 4, 5, 6]
+            .into_iter()
+            .map(|status| {
+                let torrent =
+                    serde_json::json!({"hashString": format!("{status}"), "status": status});
+                serde_json::from_value(torrent).unwrap()
+            })
+            .collect();
+
+        let hashes = |query: &str| {
+            let query: TorrentListQuery = serde_urlencoded::from_str(query).unwrap();
+            let list =
+                build_torrent_list(&state, torrents.clone(), &query, Default::default(), true);
+            let mut hashes: Vec<_> = list
+                .torrents
+                .iter()
+                .map(|x| x.hash().unwrap().to_string())
+                .collect();
+            hashes.sort_by_key(|x| x.to_string());
+            hashes
+        };
+
+        assert_eq!(hashes("").len(), 5);
+        assert_eq!(hashes("status=all").len(), 5);
+        assert_eq!(hashes("status=downloading"), ["3", "4"]);
+        assert_eq!(hashes("status=seeding"), ["5", "6"]);
+
+        // the status is kept in the sse url so that updates use the same filter
+        let query: TorrentListQuery = serde_urlencoded::from_str("status=seeding").unwrap();
+        assert_eq!(
+            serde_urlencoded::to_string(&query).unwrap(),
+            "status=seeding"
+        );
+        let query: TorrentListQuery = serde_urlencoded::from_str("status=all").unwrap();
+        assert_eq!(serde_urlencoded::to_string(&query).unwrap(), "");
+    }
+
+    #[test]
+    fn test_unread_completions() {
+        let state = test_state("");
+        let rpc = transmission::mock::test_rpc(state.config.connection.rpc_url.clone());
+        let secret = state.sessions.new_session(session::Session::new(rpc));
+        let session = state.sessions.session(secret).unwrap();
+
+        let torrents = |percent_done: f64| -> Vec<transmission::types::Torrent> {
+            let torrent = serde_json::json!({"hashString": "a", "percentDone": percent_done});
+            vec![serde_json::from_value(torrent).unwrap()]
+        };
+        let query = TorrentListQuery::default();
+        let observe = |percent_done| {
+            let torrents = torrents(percent_done);
+            build_torrent_list(&state, torrents, &query, Default::default(), false);
+        };
+
+        observe(0.5);
+        observe(1.0);
+        assert_eq!(session.unread_completions(), 1);
+
+        // an older list (for example from a cache) in between newer lists isn't another completion
+        observe(0.5);
+        observe(1.0);
+        assert_eq!(session.unread_completions(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_static_field_cache() {
+        use transmission::mock::{self, MockTransmission};
+
+        // respond with only the requested fields, like transmission
+        let mock = MockTransmission::start(|req| {
+            if req["method"] == "session-get" {
+                return mock::success(serde_json::json!({}));
+            }
+
+            let torrent = serde_json::json!({
+                "hashString": "abc",
+                "name": "Ubuntu",
+                "addedDate": 1,
+                "sizeWhenDone": 100,
+                "percentDone": 0.5,
+                "status": 4,
+                "metadataPercentComplete": 1,
+            });
+            let fields = req["arguments"]["fields"].as_array().cloned();
+            let torrent: serde_json::Map<_, _> = torrent
+                .as_object()
+                .unwrap()
+                .iter()
+                .filter(|(key, _)| {
+                    fields
+                        .as_ref()
+                        .is_some_and(|x| x.contains(&serde_json::json!(key)))
+                })
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            mock::success(serde_json::json!({"torrents": [torrent]}))
+        })
+        .await;
+
+        let state = test_state("");
+        let rpc = transmission::mock::test_rpc(mock.url());
+
+        let query = TorrentListQuery::default();
+        let mut cache = StaticFieldCache::default();
+        let requested = |mock: &MockTransmission| {
+            let bodies = mock.bodies();
+            let body = bodies
+                .iter()
+                .rfind(|x| x["method"] == "torrent-get")
+                .unwrap();
+            let fields = body["arguments"]["fields"].as_array().cloned();
+            fields.unwrap_or_default()
+        };
+
+        // the first request is for all fields
+        let list = cache
+            .torrent_list(&state, &rpc, &query, false)
+            .await
+            .unwrap();
+        assert!(requested(&mock).contains(&serde_json::json!("name")));
+        assert_eq!(list.torrents[0].name(), Some("Ubuntu"));
+
+        // later requests are only for the fields that change often, and the others are reused
+        let list = cache
+            .torrent_list(&state, &rpc, &query, false)
+            .await
+            .unwrap();
+        assert!(!requested(&mock).contains(&serde_json::json!("name")));
+        assert!(requested(&mock).contains(&serde_json::json!("percentDone")));
+        assert_eq!(list.torrents[0].name(), Some("Ubuntu"));
+        assert_eq!(list.torrents[0].percent_done(), Some(0.5));
+
+        let _list = cache
+            .torrent_list(&state, &rpc, &query, true)
+            .await
+            .unwrap();
+        assert!(requested(&mock).contains(&serde_json::json!("name")));
+    }
+
+    #[tokio::test]
+    async fn test_magnet_links() {
+        use transmission::mock::{self, MockTransmission};
+
+        let mock = MockTransmission::start(|req| {
+            // only the requested torrents are returned
+            assert_eq!(req["arguments"]["ids"], serde_json::json!(["b", "a"]));
+            mock::success(serde_json::json!({"torrents": [
+                {"name": "B", "magnetLink": "magnet:?xt=urn:btih:b"},
+                {"name": "A", "magnetLink": "magnet:?xt=urn:btih:a"},
+            ]}))
+        })
+        .await;
+
+        let state = test_state("");
+        let rpc = transmission::mock::test_rpc(mock.url());
+
+        let ids = vec!["b".to_string(), "a".to_string()];
+        let magnets = magnet_links(&state, &rpc, Some(ids)).await.unwrap();
+        assert_eq!(magnets, ["magnet:?xt=urn:btih:a", "magnet:?xt=urn:btih:b"]);
+    }
+
+    #[tokio::test]
+    async fn test_login_force_session_cookies() {
+        use transmission::mock::MockTransmission;
+
+        let mock = MockTransmission::with_arguments(serde_json::json!({
+            "version": "4.0.0",
+            "rpc-version": 17,
+        }))
+        .await;
+
+        let login_cookie = |extra: &str| {
+            let mut state = test_state(&format!("[security]\n{extra}"));
+            state.config.connection.rpc_url = mock.url();
+            let state = Arc::new(state);
+            let login = LoginQuery {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            };
+
+            async move {
+                let resp = login_post(State(state), header::HeaderMap::new(), Form(login))
+                    .await
+                    .unwrap()
+                    .into_response();
+                assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+                let cookie = &resp.headers()[header::SET_COOKIE];
+                cookie.to_str().unwrap().to_string()
+            }
+        };
+
+        assert!(login_cookie("").await.contains("; Max-Age="));
+        assert!(login_cookie("force_session_cookies = false")
+            .await
+            .contains("; Max-Age="));
+        assert!(!login_cookie("force_session_cookies = true")
+            .await
+            .contains("Max-Age"));
+    }
+
+    #[tokio::test]
+    async fn test_torrent_settings_missing_group() {
+        use transmission::mock::{self, MockTransmission};
+
+        let mock = MockTransmission::start(|req| match req["method"].as_str().unwrap() {
+            "session-get" => mock::success(serde_json::json!({
+                "version": "4.0.0",
+                "rpc-version": 17,
+            })),
+            // a torrent without a group field
+            _ => mock::success(serde_json::json!({"torrents": [{
+                "name": "Ubuntu",
+                "labels": [],
+                "downloadLimited": false,
+                "downloadLimit": 0,
+                "uploadLimited": false,
+                "uploadLimit": 0,
+                "bandwidthPriority": 0,
+                "honorsSessionLimits": true,
+            }]})),
+        })
+        .await;
+
+        let rpc = transmission::mock::test_rpc(mock.url());
+        let client = reqwest::Client::new();
+        let (_name, settings) = torrent_settings(&rpc, &client, "abc")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.group.as_deref(), Some(""));
+
+        // so a new group is sent to transmission rather than being dropped
+        let mut new_settings = settings.clone();
+        new_settings.group = Some("slow".to_string());
+        let changes = settings.changes(&new_settings);
+        assert_eq!(
+            changes,
+            transmission::types::TorrentSetArgs::new().group("slow".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_torrent_changed_elsewhere() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use transmission::mock::{self, MockTransmission};
+
+        let upload_limit = Arc::new(AtomicU64::new(100));
+        let mock = MockTransmission::start({
+            let upload_limit = Arc::clone(&upload_limit);
+            move |req| match req["method"].as_str().unwrap() {
+                "session-get" => mock::success(serde_json::json!({
+                    "version": "4.0.0",
+                    "rpc-version": 17,
+                })),
+                "torrent-get" => mock::success(serde_json::json!({"torrents": [{
+                    "name": "Ubuntu",
+                    "labels": [],
+                    "downloadLimited": false,
+                    "downloadLimit": 0,
+                    "uploadLimited": true,
+                    "uploadLimit": upload_limit.load(Ordering::SeqCst),
+                    "bandwidthPriority": 0,
+                    "honorsSessionLimits": true,
+                    "group": "",
+                }]})),
+                _ => mock::success(serde_json::json!({})),
+            }
+        })
+        .await;
+
+        let state = Arc::new(test_state(""));
+        let session = Arc::new(session::Session::new(transmission::mock::test_rpc(
+            mock.url(),
+        )));
+        let hash = "a".repeat(40);
+
+        let page = edit_torrent_get(
+            State(Arc::clone(&state)),
+            SessionArc(Arc::clone(&session)),
+            Path(hash.clone()),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let page = axum::body::to_bytes(page.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        assert!(page.contains(r#"name="original_upload_limit" value="100""#));
+
+        // the upload limit is changed by another client while the form is open
+        upload_limit.store(200, Ordering::SeqCst);
+
+        // the user only changes the labels, so the form resubmits the upload limit it was rendered
+        // with
+        let form = "labels=linux&download_limit=0&upload_limited=on&upload_limit=100\
+                    &bandwidth_priority=0&honors_session_limits=on&group=\
+                    &original_labels=&original_download_limited=false&original_download_limit=0\
+                    &original_upload_limited=true&original_upload_limit=100\
+                    &original_bandwidth_priority=0&original_honors_session_limits=true\
+                    &original_group=";
+        let form: EditTorrentForm = serde_urlencoded::from_str(form).unwrap();
+        let resp = edit_torrent_post(
+            State(Arc::clone(&state)),
+            SessionArc(Arc::clone(&session)),
+            Path(hash.clone()),
+            Form(form),
+        )
+        .await;
+        assert!(resp.is_ok());
+
+        // only the labels are set, so the other client's upload limit isn't reverted
+        let bodies = mock.bodies();
+        let set = bodies
+            .iter()
+            .find(|x| x["method"] == "torrent-set")
+            .unwrap();
+        assert_eq!(
+            set["arguments"],
+            serde_json::json!({"ids": [hash], "labels": ["linux"]}),
+        );
+    }
+
+    #[test]
+    fn test_check_magnet_lengths() {
+        assert!(check_magnet_lengths(&["magnet:?xt=urn:btih:abc"], 23).is_ok());
+        assert!(check_magnet_lengths(&["a", "magnet:?xt=urn:btih:abc"], 22).is_err());
+
+        // the length is in bytes, and the message says so
+        assert!(check_magnet_lengths(&["ééé"], 6).is_ok());
+        let err = check_magnet_lengths(&["ééé"], 5).unwrap_err();
+        assert_eq!(err, "Magnet links must be at most 5 bytes long");
+    }
+
+    #[tokio::test]
+    async fn test_torrent_favorite() {
+        use transmission::mock::{self, MockTransmission};
+
+        let existing = "a".repeat(40);
+        let mock = MockTransmission::start({
+            let existing = existing.clone();
+            move |req| {
+                let exists = req["arguments"]["ids"] == serde_json::json!([existing]);
+                let torrents: Vec<_> = exists
+                    .then(|| serde_json::json!({"hashString": existing}))
+                    .into_iter()
+                    .collect();
+                mock::success(serde_json::json!({ "torrents": torrents }))
+            }
+        })
+        .await;
+
+        let state = Arc::new(test_state(""));
+        let session = Arc::new(session::Session::new(transmission::mock::test_rpc(
+            mock.url(),
+        )));
+        let toggle = |hash: &str| {
+            let state = State(Arc::clone(&state));
+            let session = SessionArc(Arc::clone(&session));
+            torrent_favorite_post(state, session, Path(hash.to_string()))
+        };
+
+        assert_eq!(toggle("abc").await, Err(StatusCode::BAD_REQUEST));
+
+        // torrents that don't exist can't be pinned
+        assert_eq!(toggle(&"b".repeat(40)).await, Err(StatusCode::NOT_FOUND));
+        assert!(session.favorites().is_empty());
+
+        assert_eq!(toggle(&existing).await, Ok(()));
+        assert!(session.favorites().contains(&existing));
+        assert_eq!(toggle(&existing).await, Ok(()));
+        assert!(session.favorites().is_empty());
+
+        // uppercase hashes are stored the way transmission reports them
+        assert_eq!(toggle(&existing.to_uppercase()).await, Ok(()));
+        assert_eq!(session.favorites(), HashSet::from([existing.clone()]));
+        assert_eq!(toggle(&existing.to_uppercase()).await, Ok(()));
+        assert!(session.favorites().is_empty());
+    }
+
+    #[test]
+    fn test_torrent_name_matches() {
+        use transmission::types::TorrentGetKey;
+
+        let torrent =
+            |name: serde_json::Value| BTreeMap::from([(TorrentGetKey::Name, name)]).into();
+
+        assert!(torrent_name_matches(
+            &torrent("Ubuntu 24.04".into()),
+            "ubuntu"
+        ));
+        assert!(torrent_name_matches(&torrent("Ubuntu 24.04".into()), ""));
+        assert!(!torrent_name_matches(
+            &torrent("Ubuntu 24.04".into()),
+            "debian"
+        ));
+
+        // missing or non-string names shouldn't match (or panic)
+        assert!(!torrent_name_matches(&torrent(serde_json::Value::Null), ""));
+        assert!(!torrent_name_matches(&torrent(5.into()), "5"));
+        assert!(!torrent_name_matches(&Default::default(), ""));
+    }
+
+    #[test]
+    fn test_render_torrent_with_missing_fields() {
+        use transmission::types::TorrentGetKey;
+
+        // only the hash, so every other field needs a placeholder
+        let torrent: transmission::types::Torrent =
+            BTreeMap::from([(TorrentGetKey::HashString, "abc".into())]).into();
+
+        for view in [TorrentListView::Cards, TorrentListView::Table] {
+            let list = TorrentListPartialTemplate {
+                torrents: vec![torrent.clone()],
+                query: TorrentListQuery::default(),
+                view,
+                units: Default::default(),
+                unread_completions: 0,
+                favorites: HashSet::new(),
+            };
+            assert!(list.render().unwrap().contains("(unknown name)"));
+        }
+
+        let details = TorrentPartialTemplate { details: torrent };
+        assert!(details.render().unwrap().contains("(unknown name)"));
+    }
+
+    #[test]
+    fn test_torrent_url_cookies() {
+        assert!(is_torrent_url("https://tracker.example/download/1.torrent"));
+        assert!(is_torrent_url("http://127.0.0.1:8000/a.torrent"));
+        assert!(!is_torrent_url("magnet:?xt=urn:btih:abc"));
+        assert!(!is_torrent_url("file:///tmp/a.torrent"));
+        assert!(!is_torrent_url("tracker.example/a.torrent"));
+
+        assert!(is_valid_cookie_header("uid=123"));
+        assert!(is_valid_cookie_header("uid=123; pass=abc%3D"));
+        assert!(is_valid_cookie_header("empty="));
+        assert!(!is_valid_cookie_header("uid"));
+        assert!(!is_valid_cookie_header("=123"));
+        assert!(!is_valid_cookie_header("uid=123;"));
+        assert!(!is_valid_cookie_header("uid=1 23"));
+        assert!(!is_valid_cookie_header("uid=123\r\nX-Header: a"));
+        assert!(!is_valid_cookie_header(&format!(
+            "uid={}",
+            "a".repeat(5000)
+        )));
+    }
+
+    #[test]
+    fn test_api_torrents_ids() {
+        let ids = |query: &str| {
+            serde_urlencoded::from_str::<ApiTorrentsQuery>(query)
+                .unwrap()
+                .ids()
+        };
+        let a = "a".repeat(40);
+        let b = "B".repeat(64);
+
+        assert_eq!(ids(""), Ok(None));
+        assert_eq!(ids(&format!("ids={a}")), Ok(Some(vec![a.clone()])));
+        assert_eq!(
+            ids(&format!("ids={a},%20{b}")),
+            Ok(Some(vec![a.clone(), b.clone()]))
+        );
+
+        assert_eq!(ids("ids="), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(ids(&format!("ids={a},")), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(ids("ids=1"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            ids(&format!("ids={}", "g".repeat(40))),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        let too_many = vec![a; ApiTorrentsQuery::MAX_IDS + 1].join(",");
+        assert_eq!(
+            ids(&format!("ids={too_many}")),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_validate_location() {
+        assert_eq!(validate_location("/data/torrents"), Ok("/data/torrents"));
+        assert_eq!(validate_location("  /data  "), Ok("/data"));
+        assert_eq!(validate_location(r"D:\torrents"), Ok(r"D:\torrents"));
+        assert_eq!(validate_location("c:/torrents"), Ok("c:/torrents"));
+        assert!(validate_location("").is_err());
+        assert!(validate_location("   ").is_err());
+        assert!(validate_location("torrents").is_err());
+        assert!(validate_location("./torrents").is_err());
+        assert!(validate_location("1:/torrents").is_err());
+        assert!(validate_location("/data\ntorrents").is_err());
+    }
+
+    #[test]
+    fn test_sort_torrents() {
+        use transmission::types::TorrentGetKey;
+
+        let torrent =
+            |added: u64| BTreeMap::from([(TorrentGetKey::AddedDate, added.into())]).into();
+        let added = |torrents: &[transmission::types::Torrent]| {
+            torrents
+                .iter()
+                .map(|x| x.added_date().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut torrents = vec![torrent(2), torrent(3), torrent(1)];
+
+        sort_torrents(&mut torrents, TorrentSortField::DateAdded, false);
+        assert_eq!(added(&torrents), [3, 2, 1]);
+
+        sort_torrents(&mut torrents, TorrentSortField::DateAdded, true);
+        assert_eq!(added(&torrents), [1, 2, 3]);
+
+        // torrents with an unknown eta are last in both directions
+        let torrent = |added: u64, eta: i64| {
+            BTreeMap::from([
+                (TorrentGetKey::AddedDate, added.into()),
+                (TorrentGetKey::Eta, eta.into()),
+            ])
+            .into()
+        };
+        let mut torrents = vec![torrent(1, 60), torrent(2, -1), torrent(3, 10)];
+
+        sort_torrents(&mut torrents, TorrentSortField::Eta, true);
+        assert_eq!(added(&torrents), [3, 1, 2]);
+
+        sort_torrents(&mut torrents, TorrentSortField::Eta, false);
+        assert_eq!(added(&torrents), [1, 3, 2]);
+    }
+
+    #[test]
+    fn test_pin_favorites() {
+        use transmission::types::TorrentGetKey;
+
+        let hash = |x: char| x.to_string().repeat(40);
+        let torrent = |x| BTreeMap::from([(TorrentGetKey::HashString, hash(x).into())]).into();
+
+        let mut list = TorrentListPartialTemplate {
+            torrents: vec![torrent('a'), torrent('b'), torrent('c'), torrent('d')],
+            query: TorrentListQuery::default(),
+            view: TorrentListView::Cards,
+            units: Default::default(),
+            unread_completions: 0,
+            favorites: HashSet::new(),
+        };
+        list.pin_favorites(HashSet::from([hash('c'), hash('b')]));
+
+        // the favorites are first, but otherwise keep their order
+        let hashes: Vec<_> = list.torrents.iter().map(|x| x.hash().unwrap()).collect();
+        assert_eq!(hashes, [hash('b'), hash('c'), hash('a'), hash('d')]);
 
-    Ok(session::SessionSecret::new(session_secret))
+        assert!(list.is_favorite(&list.torrents[0]));
+        assert!(!list.is_favorite(&list.torrents[2]));
+        assert!(list
+            .render()
+            .unwrap()
+            .contains("Unpin from the top of the list"));
+    }
+
+    #[test]
+    fn test_render_empty_torrent_list() {
+        let list = |query| TorrentListPartialTemplate {
+            torrents: Vec::new(),
+            query,
+            view: TorrentListView::Cards,
+            units: Default::default(),
+            unread_completions: 0,
+            favorites: HashSet::new(),
+        };
+
+        let html = list(TorrentListQuery::default()).render().unwrap();
+        assert!(html.contains("No torrents yet"));
+
+        let query = TorrentListQuery {
+            filter: Some("<foo>".to_string()),
+            ..Default::default()
+        };
+        let html = list(query).render().unwrap();
+        assert!(html.contains("No torrents match \"&lt;foo&gt;\""));
+
+        let query = TorrentListQuery {
+            show: TorrentListShow::Attention,
+            ..Default::default()
+        };
+        let html = list(query).render().unwrap();
+        assert!(html.contains("No torrents need attention"));
+    }
+
+    #[test]
+    fn test_render_torrent_status() {
+        let status = |details: serde_json::Value| {
+            let details = serde_json::from_value(details).unwrap();
+            TorrentStatusPartialTemplate { details }.render().unwrap()
+        };
+
+        let html = status(serde_json::json!({"status": 4, "isStalled": false, "error": 0}));
+        assert!(html.contains("Status: Downloading"));
+        assert!(!html.contains("Stalled"));
+        assert!(!html.contains("error-badge"));
+
+        let html = status(serde_json::json!({
+            "status": 0,
+            "isStalled": true,
+            "error": 3,
+            "errorString": "No data found",
+        }));
+        assert!(html.contains("Stalled"));
+        assert!(html.contains(r#"class="error-badge" title="No data found""#));
+
+        // missing fields shouldn't prevent rendering
+        let html = status(serde_json::json!({}));
+        assert!(html.contains("Status: Unknown"));
+
+        // the details should include the same status
+        for key in TorrentStatusPartialTemplate::KEYS {
+            assert!(TorrentPartialTemplate::KEYS.contains(key));
+        }
+    }
+
+    #[test]
+    fn test_sort_url() {
+        let query = TorrentListQuery::default();
+        assert!(!query.ascending());
+        assert_eq!(
+            query.sort_url(&TorrentSortField::DateAdded),
+            "/?sort=date-added&dir=ascend"
+        );
+        assert_eq!(
+            query.sort_url(&TorrentSortField::Name),
+            "/?sort=name&dir=ascend"
+        );
+
+        // the direction toggles and other parameters are kept
+        let query = TorrentListQuery {
+            filter: Some("linux".to_string()),
+            sort: Some(TorrentSortField::Name),
+            ..Default::default()
+        };
+        assert!(query.ascending());
+        assert_eq!(
+            query.sort_url(&TorrentSortField::Name),
+            "/?q=linux&sort=name&dir=descend"
+        );
+        assert_eq!(
+            query.sort_url(&TorrentSortField::Size),
+            "/?q=linux&sort=size&dir=descend"
+        );
+    }
 }
@@ -1,19 +1,26 @@
+use askama::Template;
 use axum::body::{Body, BodyDataStream, Bytes, HttpBody};
-use axum::extract::Request;
+use axum::extract::{Request, State};
 use axum::http::{header, StatusCode};
 use axum::middleware::Next;
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use futures_util::stream::Stream;
 
+use crate::transmission::rpc::RPC_DURATION;
+
+use std::cell::Cell;
 use std::io::Write;
 use std::pin::{pin, Pin};
 use std::task::Context;
 use std::task::Poll;
 
+/// Replace empty "401 Unauthorized" responses with a page that redirects to the login page, if the
+/// request came from a browser. API requests always get a plain 401 response.
 pub async fn unauthorized_redirect(request: Request, next: Next) -> Response {
     let accept = request.headers().get(header::ACCEPT).cloned();
+    let is_api = is_api_path(request.uri().path());
 
     let mut response = next.run(request).await;
 
@@ -37,7 +44,7 @@ pub async fn unauthorized_redirect(request: Request, next: Next) -> Response {
             true
         };
 
-        if is_empty && request_allows_html && response_can_be_html {
+        if !is_api && is_empty && request_allows_html && response_can_be_html {
             let html =
                 r#"<meta http-equiv="refresh" content="0; url=/login"> Unauthorized. Redirecting."#;
             *response.body_mut() = Body::from(html);
@@ -58,6 +65,205 @@ pub async fn unauthorized_redirect(request: Request, next: Next) -> Response {
     response
 }
 
+/// Replace empty "504 Gateway Timeout" responses (which are returned when transmission can't be
+/// reached) with a page explaining that transmission is unavailable, if the request came from a
+/// browser.
+pub async fn unavailable_page(
+    State(display_name): State<String>,
+    request: Request,
+    next: Next,
+) -> Response {
+    #[derive(Template)]
+    #[template(path = "unavailable.html")]
+    struct UnavailableTemplate {
+        display_name: String,
+    }
+
+    let accept = request.headers().get(header::ACCEPT).cloned();
+    let is_api = is_api_path(request.uri().path());
+
+    let mut response = next.run(request).await;
+
+    if response.status() != StatusCode::GATEWAY_TIMEOUT || is_api {
+        return response;
+    }
+
+    if !response.body().is_end_stream() || response.headers().contains_key(header::CONTENT_TYPE) {
+        return response;
+    }
+
+    // only for page navigations, not for requests made by scripts on the page
+    let request_allows_html = accept
+        .and_then(|x| {
+            x.to_str()
+                .ok()
+                .map(|x| x.split(',').any(|x| x == "text/html"))
+        })
+        .unwrap_or(false);
+    if !request_allows_html {
+        return response;
+    }
+
+    let Ok(html) = (UnavailableTemplate { display_name }).render() else {
+        return response;
+    };
+
+    *response.body_mut() = Body::from(html);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.remove(header::CONTENT_LENGTH);
+
+    response
+}
+
+/// Error response bodies larger than this aren't checked by `redact_session_cookie`. Error messages
+/// are short, so there's no need to buffer large or streaming bodies.
+const MAX_REDACTED_BODY_LEN: u64 = 64 * 1024;
+
+/// Make sure that error responses never contain the request's session secret, in case an error
+/// message ever includes a request header. Successful responses are rendered from transmission's
+/// data, so aren't checked.
+pub async fn redact_session_cookie(request: Request, next: Next) -> Response {
+    let secrets: Vec<String> = request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(cookie::Cookie::split_parse)
+        .filter_map(Result::ok)
+        .filter(|x| x.name() == "session_secret" && !x.value().is_empty())
+        .map(|x| x.value().to_string())
+        .collect();
+
+    let response = next.run(request).await;
+
+    let is_error = response.status().is_client_error() || response.status().is_server_error();
+    let is_small = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|x| x <= MAX_REDACTED_BODY_LEN);
+
+    if secrets.is_empty() || !is_error || !is_small {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    // the size hint is an upper bound, so this shouldn't fail
+    let Ok(body) = axum::body::to_bytes(body, MAX_REDACTED_BODY_LEN as usize).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let contains = |x: &[u8]| body.windows(x.len()).any(|window| window == x);
+    if !secrets.iter().any(|x| contains(x.as_bytes())) {
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    println!("Removed a session secret from an error response");
+
+    let mut body = String::from_utf8_lossy(&body).into_owned();
+    for secret in &secrets {
+        body = body.replace(secret.as_str(), "<redacted>");
+    }
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Whether the path is for the API used by scripts, rather than a page that a browser navigates to.
+fn is_api_path(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+/// Add a `X-RPC-Duration-Ms` header with the total time spent on transmission requests, if enabled
+/// and if the handler made any transmission requests.
+pub async fn rpc_duration_header(
+    State(enabled): State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !enabled {
+        return next.run(request).await;
+    }
+
+    let (mut response, rpc_duration) = RPC_DURATION
+        .scope(Cell::new(None), async {
+            let response = next.run(request).await;
+            (response, RPC_DURATION.with(Cell::get))
+        })
+        .await;
+
+    if let Some(rpc_duration) = rpc_duration {
+        let value = rpc_duration.as_millis().to_string();
+        // the string is only digits so is always a valid header value
+        let value = header::HeaderValue::from_str(&value).unwrap();
+        response.headers_mut().insert("X-RPC-Duration-Ms", value);
+    }
+
+    response
+}
+
+/// Prevent the response from being stored by browsers or proxies, replacing any caching headers
+/// set by the handler.
+pub async fn no_store(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    for name in [
+        header::EXPIRES,
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::AGE,
+    ] {
+        headers.remove(name);
+    }
+
+    headers.insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+
+    response
+}
+
+/// Add headers to SSE responses so that events aren't buffered or cached along the way. If
+/// `disable_proxy_buffering` is set, this adds a `X-Accel-Buffering: no` header, which nginx
+/// (including nginx-based proxies) needs to forward each event as soon as it's sent.
+pub async fn sse_headers(
+    State(disable_proxy_buffering): State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    let is_sse = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|x| trim_whitespace(x.as_bytes()) == b"text/event-stream");
+    if !is_sse {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+
+    if !headers.contains_key(header::CACHE_CONTROL) {
+        headers.insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-cache"),
+        );
+    }
+
+    if disable_proxy_buffering {
+        headers.insert("X-Accel-Buffering", header::HeaderValue::from_static("no"));
+    }
+
+    response
+}
+
 pub async fn compress_sse(request: Request, next: Next) -> Response {
     let accept_encoding = request.headers().get(header::ACCEPT_ENCODING).cloned();
 
@@ -79,12 +285,11 @@ pub async fn compress_sse(request: Request, next: Next) -> Response {
     }
 
     if let Some(accept_encoding) = accept_encoding {
-        // if no accepted encoding options are gzip
-        if accept_encoding
-            .as_bytes()
-            .split(|x| *x == b","[0])
-            .all(|x| trim_whitespace(x) != b"gzip")
-        {
+        // if the client doesn't accept gzip
+        let Ok(accept_encoding) = accept_encoding.to_str() else {
+            return response;
+        };
+        if encoding_quality(accept_encoding, "gzip") <= 0.0 {
             return response;
         }
     } else {
@@ -102,9 +307,28 @@ pub async fn compress_sse(request: Request, next: Next) -> Response {
         header::HeaderValue::from_static("gzip"),
     );
 
+    // tower-http's `CompressionLayer` adds this for the responses that it compresses, but it
+    // doesn't compress sse streams so we need to add it ourselves
+    add_vary(&mut parts.headers, header::ACCEPT_ENCODING);
+
     Response::from_parts(parts, body)
 }
 
+/// Add a header name to the `Vary` header, unless it's already present.
+fn add_vary(headers: &mut header::HeaderMap, name: header::HeaderName) {
+    let already_varies = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .map(str::trim)
+        .any(|x| x == "*" || x.eq_ignore_ascii_case(name.as_str()));
+
+    if !already_varies {
+        headers.append(header::VARY, name.into());
+    }
+}
+
 struct CompressedStream {
     inner: BodyDataStream,
     compression: GzEncoder<Vec<u8>>,
@@ -139,6 +363,50 @@ impl Stream for CompressedStream {
     }
 }
 
+/// Parse an `Accept-Encoding` header into its codings and their quality values. Codings without a
+/// quality value have a quality of 1, and codings with an invalid quality value have a quality of
+/// 0.
+fn parse_accept_encoding(accept_encoding: &str) -> impl Iterator<Item = (&str, f32)> {
+    accept_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let mut params = x.split(';');
+            // `split` always returns at least one item
+            let coding = params.next().unwrap().trim();
+
+            let quality = params
+                .map(str::trim)
+                .find_map(|x| x.strip_prefix("q=").or_else(|| x.strip_prefix("Q=")))
+                .map(|x| match x.trim().parse::<f32>() {
+                    Ok(x) if (0.0..=1.0).contains(&x) => x,
+                    _ => 0.0,
+                })
+                .unwrap_or(1.0);
+
+            (coding, quality)
+        })
+}
+
+/// The quality value that an `Accept-Encoding` header gives to a coding such as "gzip". A coding
+/// that is listed explicitly takes precedence over the "*" wildcard, and a quality of 0 means that
+/// the coding must not be used.
+fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+
+    for (name, quality) in parse_accept_encoding(accept_encoding) {
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+
+    wildcard.unwrap_or(0.0)
+}
+
 fn trim_whitespace(bytes: &[u8]) -> &[u8] {
     let start = bytes
         .iter()
@@ -174,4 +442,352 @@ mod tests {
         assert_eq!(trim_whitespace(b"\thello world\t"), b"hello world");
         assert_eq!(trim_whitespace(b" \t hello world \t "), b"hello world");
     }
+
+    #[test]
+    fn test_add_vary() {
+        let vary = |headers: &header::HeaderMap| {
+            headers
+                .get_all(header::VARY)
+                .iter()
+                .map(|x| x.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut headers = header::HeaderMap::new();
+        add_vary(&mut headers, header::ACCEPT_ENCODING);
+        assert_eq!(vary(&headers), ["accept-encoding"]);
+
+        // don't add it twice
+        add_vary(&mut headers, header::ACCEPT_ENCODING);
+        assert_eq!(vary(&headers), ["accept-encoding"]);
+
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::VARY, "Cookie, Accept-Encoding".parse().unwrap());
+        add_vary(&mut headers, header::ACCEPT_ENCODING);
+        assert_eq!(vary(&headers), ["Cookie, Accept-Encoding"]);
+
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::VARY, "Cookie".parse().unwrap());
+        add_vary(&mut headers, header::ACCEPT_ENCODING);
+        assert_eq!(vary(&headers), ["Cookie", "accept-encoding"]);
+
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::VARY, "*".parse().unwrap());
+        add_vary(&mut headers, header::ACCEPT_ENCODING);
+        assert_eq!(vary(&headers), ["*"]);
+    }
+
+    #[tokio::test]
+    async fn test_compress_sse_vary() {
+        use tower::Service;
+
+        let mut app = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async {
+                    ([(header::CONTENT_TYPE, "text/event-stream")], "")
+                }),
+            )
+            .layer(axum::middleware::from_fn(compress_sse));
+
+        let request = |accept_encoding| {
+            Request::builder()
+                .uri("/")
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.call(request("gzip")).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+
+        let response = app.call(request("identity")).await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_stream_framing() {
+        use futures_util::StreamExt;
+
+        let events = [
+            "event: list\ndata: <ul class=\"torrent-list\">\ndata: </ul>\n\n".to_string(),
+            ":\n\n".to_string(),
+            "event: heartbeat\ndata: \n\n".to_string(),
+            format!(
+                "event: rows\ndata: {}\n\n",
+                "<li>Ubuntu ✓</li>".repeat(1000)
+            ),
+            "event: removed\ndata: <b>Removed</b>\n\n".to_string(),
+        ];
+
+        let body = futures_util::stream::iter(
+            events
+                .clone()
+                .map(|x| Ok::<_, std::convert::Infallible>(Bytes::from(x))),
+        );
+        let mut stream = CompressedStream::new(Body::from_stream(body).into_data_stream());
+
+        // the stream never finishes the gzip member (the connection is just closed), so decode it
+        // incrementally rather than expecting a complete gzip file
+        let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+        let mut expected = String::new();
+
+        for event in &events {
+            let chunk = stream.next().await.unwrap().unwrap();
+            assert!(!chunk.is_empty());
+
+            // each event must be fully decodable as soon as its chunk is received, otherwise the
+            // browser wouldn't see the event until a later one arrives
+            decoder.write_all(&chunk).unwrap();
+            decoder.flush().unwrap();
+            expected.push_str(event);
+            assert_eq!(std::str::from_utf8(decoder.get_ref()).unwrap(), expected);
+        }
+
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sse_headers() {
+        use tower::Service;
+
+        let app = |disable_proxy_buffering| {
+            axum::Router::new()
+                .route(
+                    "/sse",
+                    axum::routing::get(|| async {
+                        ([(header::CONTENT_TYPE, "text/event-stream")], "")
+                    }),
+                )
+                .route("/html", axum::routing::get(|| async { "" }))
+                .layer(axum::middleware::from_fn_with_state(
+                    disable_proxy_buffering,
+                    sse_headers,
+                ))
+        };
+
+        let request = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app(true).call(request("/sse")).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(response.headers()["X-Accel-Buffering"], "no");
+
+        let response = app(false).call(request("/sse")).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        assert!(!response.headers().contains_key("X-Accel-Buffering"));
+
+        let response = app(true).call(request("/html")).await.unwrap();
+        assert!(!response.headers().contains_key(header::CACHE_CONTROL));
+        assert!(!response.headers().contains_key("X-Accel-Buffering"));
+    }
+
+    #[tokio::test]
+    async fn test_no_store() {
+        use tower::Service;
+
+        let mut app = axum::Router::new()
+            .route(
+                "/cached",
+                axum::routing::get(|| async {
+                    (
+                        [
+                            (header::CACHE_CONTROL, "max-age=3600"),
+                            (header::ETAG, "\"abc\""),
+                        ],
+                        "",
+                    )
+                }),
+            )
+            .route("/plain", axum::routing::get(|| async { "" }))
+            .layer(axum::middleware::from_fn(no_store));
+
+        let request = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.call(request("/cached")).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert!(!response.headers().contains_key(header::ETAG));
+
+        let response = app.call(request("/plain")).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[test]
+    fn test_is_api_path() {
+        assert!(is_api_path("/api"));
+        assert!(is_api_path("/api/"));
+        assert!(is_api_path("/api/version"));
+        assert!(!is_api_path("/"));
+        assert!(!is_api_path("/apiary"));
+        assert!(!is_api_path("/torrent/api"));
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_redirect() {
+        use tower::Service;
+
+        let mut app = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async { StatusCode::UNAUTHORIZED }),
+            )
+            .route(
+                "/api/version",
+                axum::routing::get(|| async { StatusCode::UNAUTHORIZED }),
+            )
+            .layer(axum::middleware::from_fn(unauthorized_redirect));
+
+        let request = |uri, accept| {
+            Request::builder()
+                .uri(uri)
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.call(request("/", "text/html")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+
+        let response = app.call(request("/", "application/json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response.headers().contains_key(header::CONTENT_TYPE));
+
+        // api requests never get the html redirect, even if they accept html
+        let response = app
+            .call(request("/api/version", "text/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response.headers().contains_key(header::CONTENT_TYPE));
+        assert!(response.into_body().is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_page() {
+        use tower::Service;
+
+        let mut app = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async { StatusCode::GATEWAY_TIMEOUT }),
+            )
+            .route(
+                "/bad",
+                axum::routing::get(|| async { StatusCode::BAD_GATEWAY }),
+            )
+            .route(
+                "/api/counts",
+                axum::routing::get(|| async { StatusCode::GATEWAY_TIMEOUT }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                "Home".to_string(),
+                unavailable_page,
+            ));
+
+        let request = |uri, accept| {
+            Request::builder()
+                .uri(uri)
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let body = |response: Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let response = app.call(request("/", "text/html,*/*")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let html = body(response).await;
+        assert!(html.contains("Transmission is unavailable"));
+        assert!(html.contains("Home"));
+
+        // other errors, api requests, and non-browser requests are unchanged
+        for (uri, accept) in [
+            ("/bad", "text/html"),
+            ("/api/counts", "text/html"),
+            ("/", "application/json"),
+        ] {
+            let response = app.call(request(uri, accept)).await.unwrap();
+            assert!(!response.headers().contains_key(header::CONTENT_TYPE));
+            assert_eq!(body(response).await, "");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redact_session_cookie() {
+        use tower::Service;
+
+        // echo the request's headers in an error, which no handler should do
+        let echo = |status: StatusCode| {
+            move |headers: header::HeaderMap| async move { (status, format!("{headers:?}")) }
+        };
+
+        let mut app = axum::Router::new()
+            .route(
+                "/error",
+                axum::routing::get(echo(StatusCode::INTERNAL_SERVER_ERROR)),
+            )
+            .route("/missing", axum::routing::get(echo(StatusCode::NOT_FOUND)))
+            .route("/ok", axum::routing::get(echo(StatusCode::OK)))
+            .layer(axum::middleware::from_fn(redact_session_cookie));
+
+        let secret = "000000000000000000000000000000000012345";
+        let request = |uri| {
+            Request::builder()
+                .uri(uri)
+                .header(
+                    header::COOKIE,
+                    format!("view=cards; session_secret={secret}"),
+                )
+                .body(Body::empty())
+                .unwrap()
+        };
+        let body = |response: Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        for uri in ["/error", "/missing"] {
+            let response = app.call(request(uri)).await.unwrap();
+            assert!(response.status().is_client_error() || response.status().is_server_error());
+            let body = body(response).await;
+            assert!(!body.contains(secret));
+            assert!(body.contains("session_secret=<redacted>"));
+            assert!(body.contains("view=cards"));
+        }
+
+        // successful responses are unchanged
+        let response = app.call(request("/ok")).await.unwrap();
+        assert!(body(response).await.contains(secret));
+    }
+
+    #[test]
+    fn test_encoding_quality() {
+        let gzip = |x| encoding_quality(x, "gzip");
+
+        assert_eq!(gzip(""), 0.0);
+        assert_eq!(gzip("gzip"), 1.0);
+        assert_eq!(gzip("GZIP"), 1.0);
+        assert_eq!(gzip("gzip, deflate, br"), 1.0);
+        assert_eq!(gzip("deflate,gzip"), 1.0);
+        assert_eq!(gzip("br, deflate"), 0.0);
+        assert_eq!(gzip("identity"), 0.0);
+        assert_eq!(gzip("gzip;q=0"), 0.0);
+        assert_eq!(gzip("gzip; q=0.5"), 0.5);
+        assert_eq!(gzip("gzip;Q=0.5"), 0.5);
+        assert_eq!(gzip("gzip;q=abc"), 0.0);
+        assert_eq!(gzip("gzip;q=2"), 0.0);
+        assert_eq!(gzip("*"), 1.0);
+        assert_eq!(gzip("*;q=0"), 0.0);
+        assert_eq!(gzip("identity;q=0, *"), 1.0);
+        assert_eq!(gzip("*, gzip;q=0"), 0.0);
+        assert_eq!(gzip("gzip;q=0.8, *;q=0"), 0.8);
+        assert_eq!(gzip("br;q=1.0, gzip;q=0.8, *;q=0.1"), 0.8);
+    }
 }
@@ -0,0 +1,200 @@
+use serde::Serialize;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{ConfigNotifications, NotificationEvent};
+use crate::transmission::types::Torrent;
+
+/// Sends webhook notifications when torrents change. Changes are detected by comparing each
+/// observed torrent list with the previously observed list, so notifications are only sent while
+/// something (such as an SSE connection) is polling Transmission.
+#[derive(Debug)]
+pub struct Notifier {
+    url: Option<reqwest::Url>,
+    events: Vec<NotificationEvent>,
+    timeout: Duration,
+    /// Whether each torrent that has been observed has also been observed as complete. This is
+    /// `None` until the torrent list is first observed, so that we don't send notifications for
+    /// all existing torrents on startup. Torrent lists may be observed out of order (for example
+    /// a cached list after a newer one), so a torrent only ever goes from incomplete to complete,
+    /// and torrents are only forgotten when they're missing from a list that was fetched in full.
+    seen: Mutex<Option<HashMap<String, bool>>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: NotificationEvent,
+    name: &'a str,
+    hash: &'a str,
+    /// The size of the wanted files in bytes.
+    size: u64,
+}
+
+impl Notifier {
+    pub fn new(config: &ConfigNotifications) -> Self {
+        Self {
+            url: config.webhook_url.clone(),
+            events: config.webhook_events.clone(),
+            timeout: Duration::from_millis(config.webhook_timeout_ms),
+            seen: Mutex::new(None),
+        }
+    }
+
+    /// Compare the complete (unfiltered) torrent list with the previously observed lists and send
+    /// notifications for any changes. Each event is sent at most once per torrent. The torrents
+    /// should have at least the keys from `TorrentListView::REQUIRED_KEYS`. Notifications are sent
+    /// in the background and failures are only logged. Returns the number of torrents that
+    /// completed since they were last observed, even if no webhook is configured.
+    ///
+    /// `fetched_in_full` should be set if every torrent in the list was just fetched from
+    /// transmission (rather than partly coming from a cache), so that torrents missing from it
+    /// were removed and can be forgotten.
+    pub fn observe(
+        &self,
+        client: &reqwest::Client,
+        torrents: &[Torrent],
+        fetched_in_full: bool,
+    ) -> usize {
+        let mut events = Vec::new();
+
+        {
+            let mut seen = self.seen.lock().unwrap();
+            let is_first = seen.is_none();
+            let seen = seen.get_or_insert_with(HashMap::new);
+
+            for torrent in torrents {
+                let (Some(hash), Some(percent_done)) = (torrent.hash(), torrent.percent_done())
+                else {
+                    continue;
+                };
+                let done = percent_done >= 1.0;
+
+                let event = match seen.get_mut(hash) {
+                    None => {
+                        seen.insert(hash.to_string(), done);
+                        NotificationEvent::Added
+                    }
+                    Some(seen_done) if !*seen_done && done => {
+                        *seen_done = true;
+                        NotificationEvent::Completed
+                    }
+                    Some(_) => continue,
+                };
+
+                if !is_first {
+                    events.push((event, torrent));
+                }
+            }
+
+            if fetched_in_full {
+                let hashes: HashSet<_> = torrents.iter().filter_map(Torrent::hash).collect();
+                seen.retain(|hash, _| hashes.contains(hash.as_str()));
+            }
+        }
+
+        let mut completed = 0;
+
+        for (event, torrent) in events {
+            if event == NotificationEvent::Completed {
+                completed += 1;
+            }
+
+            let Some(url) = &self.url else {
+                continue;
+            };
+
+            if !self.events.contains(&event) {
+                continue;
+            }
+
+            let payload = Payload {
+                event,
+                name: torrent.name().unwrap_or_default(),
+                hash: torrent.hash().unwrap_or_default(),
+                size: torrent.size_when_done().unwrap_or_default(),
+            };
+
+            let request = client
+                .post(url.clone())
+                .timeout(self.timeout)
+                .json(&payload);
+
+            // don't block the poll loop on the webhook
+            tokio::spawn(async move {
+                let rv = request.send().await.and_then(|x| x.error_for_status());
+                if let Err(e) = rv {
+                    println!("Failed to send webhook notification: {e}");
+                }
+            });
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transmission::types::TorrentGetKey;
+
+    fn torrents(list: &[(&str, f64)]) -> Vec<Torrent> {
+        list.iter()
+            .map(|(hash, percent_done)| {
+                Torrent::from(std::collections::BTreeMap::from([
+                    (TorrentGetKey::HashString, serde_json::json!(hash)),
+                    (TorrentGetKey::PercentDone, serde_json::json!(percent_done)),
+                ]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_observe_out_of_order() {
+        let notifier = Notifier::new(&ConfigNotifications::default());
+        let client = reqwest::Client::new();
+
+        let old = torrents(&[("a", 0.5), ("b", 0.5)]);
+        let new = torrents(&[("a", 1.0), ("b", 0.5), ("c", 0.0)]);
+
+        // the first list is only used as a starting point
+        assert_eq!(notifier.observe(&client, &old, false), 0);
+        assert_eq!(notifier.observe(&client, &new, false), 1);
+
+        // a stale list followed by a newer one shouldn't report the completion again
+        assert_eq!(notifier.observe(&client, &old, false), 0);
+        assert_eq!(notifier.observe(&client, &new, false), 0);
+
+        let newer = torrents(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        assert_eq!(notifier.observe(&client, &newer, false), 2);
+        assert_eq!(notifier.observe(&client, &new, false), 0);
+        assert_eq!(notifier.observe(&client, &newer, false), 0);
+    }
+    #[test]
+    fn test_observe_forgets_removed() {
+        let notifier = Notifier::new(&ConfigNotifications::default());
+        let client = reqwest::Client::new();
+
+        notifier.observe(&client, &torrents(&[("a", 0.5), ("b", 0.5)]), true);
+        let seen = || {
+            let seen = notifier.seen.lock().unwrap();
+            let mut hashes: Vec<_> = seen.as_ref().unwrap().keys().cloned().collect();
+            hashes.sort();
+            hashes
+        };
+
+        // a partly cached list may be missing torrents that still exist
+        notifier.observe(&client, &torrents(&[("a", 0.5)]), false);
+        assert_eq!(seen(), ["a", "b"]);
+
+        notifier.observe(&client, &torrents(&[("a", 0.5)]), true);
+        assert_eq!(seen(), ["a"]);
+
+        // a torrent that's added again is new
+        let b = torrents(&[("a", 0.5), ("b", 1.0)]);
+        assert_eq!(notifier.observe(&client, &b, true), 0);
+        assert_eq!(seen(), ["a", "b"]);
+    }
+}
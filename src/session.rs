@@ -1,23 +1,59 @@
 use rand::Rng;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
-use std::time::{Duration, SystemTime};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug)]
 pub struct Session<T> {
     data: T,
-    expires: SystemTime,
+    /// A non-secret identifier for the session, so that it can be referred to (for example to
+    /// revoke it) without knowing its secret.
+    id: u64,
+    created: SystemTime,
+    expires: RwLock<SystemTime>,
+    /// The session never expires later than this, even if its expiration time is extended.
+    max_expires: SystemTime,
+    /// The session expires if it isn't used for this long.
+    idle_timeout: Option<Duration>,
+    /// When the session was last used.
+    last_seen: Mutex<Instant>,
+    /// The number of open streams (for example SSE connections) using this session.
+    streams: AtomicUsize,
+    /// A message to show the user the next time a page is loaded.
+    flash: Mutex<Option<String>>,
+    /// Recently submitted forms (for example added magnet links) and when they were submitted.
+    submissions: Mutex<HashMap<String, Instant>>,
+    /// The number of torrents that completed since the user last viewed the torrent list.
+    unread_completions: AtomicUsize,
+    /// Hashes of the torrents that the user pinned to the top of the torrent list.
+    favorites: Mutex<HashSet<String>>,
 }
 
 impl<T> Session<T> {
     pub fn new(data: T) -> Self {
         // approximately 4 months
         const EXPIRES: Duration = Duration::from_secs(60 * 60 * 24 * 30 * 4);
-        let expires = SystemTime::now().checked_add(EXPIRES).unwrap();
+        let now = SystemTime::now();
+        let expires = now.checked_add(EXPIRES).unwrap();
 
-        Self { data, expires }
+        Self {
+            data,
+            id: rand::thread_rng().gen(),
+            created: now,
+            expires: RwLock::new(expires),
+            max_expires: expires,
+            idle_timeout: None,
+            last_seen: Mutex::new(Instant::now()),
+            streams: AtomicUsize::new(0),
+            flash: Mutex::new(None),
+            submissions: Mutex::new(HashMap::new()),
+            unread_completions: AtomicUsize::new(0),
+            favorites: Mutex::new(HashSet::new()),
+        }
     }
 
     #[inline]
@@ -29,18 +65,174 @@ impl<T> Session<T> {
         &self.data
     }
 
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    /// How long ago the session was last used (see `touch()`).
+    pub fn last_seen(&self) -> Duration {
+        self.last_seen.lock().unwrap().elapsed()
+    }
+
+    /// The number of open streams using this session.
+    pub fn streams(&self) -> usize {
+        self.streams.load(Ordering::Relaxed)
+    }
+
+    /// Expire the session if it isn't used (see `touch()`) for `timeout`.
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
     pub fn expired(&self) -> bool {
-        SystemTime::now() >= self.expires
+        let idle = self
+            .idle_timeout
+            .is_some_and(|timeout| self.last_seen.lock().unwrap().elapsed() >= timeout);
+
+        idle || SystemTime::now() >= self.expires()
+    }
+
+    /// Expire the session immediately, for example if its credentials stopped working.
+    pub fn expire(&self) {
+        *self.expires.write().unwrap() = SystemTime::now();
+    }
+
+    /// Record that the session was used, which resets its idle timeout.
+    pub fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
     }
 
     pub fn expires(&self) -> SystemTime {
-        self.expires
+        *self.expires.read().unwrap()
+    }
+
+    /// The latest time that the session could expire, if its expiration time keeps being
+    /// refreshed.
+    pub fn max_expires(&self) -> SystemTime {
+        self.max_expires
+    }
+
+    /// Set the session to expire `window` from now, but no later than `max_expires()`. This may
+    /// make the session expire earlier than it would have.
+    pub fn refresh_expiry(&self, window: Duration) {
+        let expires = SystemTime::now()
+            .checked_add(window)
+            .map_or(self.max_expires, |x| x.min(self.max_expires));
+        *self.expires.write().unwrap() = expires;
+    }
+
+    /// Set a message to show the user the next time a page is loaded. Replaces any existing
+    /// message.
+    pub fn set_flash(&self, msg: String) {
+        *self.flash.lock().unwrap() = Some(msg);
+    }
+
+    /// Take the message to show the user, if any.
+    pub fn take_flash(&self) -> Option<String> {
+        self.flash.lock().unwrap().take()
+    }
+
+    /// The number of torrents that completed since the user last viewed the torrent list.
+    pub fn unread_completions(&self) -> usize {
+        self.unread_completions.load(Ordering::Relaxed)
+    }
+
+    pub fn add_unread_completions(&self, count: usize) {
+        self.unread_completions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Reset the unread completions, for example when the user views the torrent list.
+    pub fn clear_unread_completions(&self) {
+        self.unread_completions.store(0, Ordering::Relaxed);
+    }
+
+    /// Hashes of the torrents that the user pinned to the top of the torrent list.
+    pub fn favorites(&self) -> HashSet<String> {
+        self.favorites.lock().unwrap().clone()
+    }
+
+    /// Pin the torrent to the top of the torrent list, or unpin it if it's already pinned. Returns
+    /// whether the torrent is now pinned.
+    pub fn toggle_favorite(&self, hash: &str) -> bool {
+        let mut favorites = self.favorites.lock().unwrap();
+
+        if favorites.remove(hash) {
+            return false;
+        }
+
+        favorites.insert(hash.to_string());
+        true
+    }
+
+    /// Record that `key` was submitted. Returns false if it was already submitted within the
+    /// last `window`, which can be used to ignore double-submitted forms.
+    pub fn record_submission(&self, key: &str, window: Duration) -> bool {
+        let mut submissions = self.submissions.lock().unwrap();
+
+        submissions.retain(|_, time| time.elapsed() < window);
+
+        if submissions.contains_key(key) {
+            return false;
+        }
+
+        submissions.insert(key.to_string(), Instant::now());
+        true
+    }
+
+    /// Forget that `key` was submitted, for example if the submission failed and the user should
+    /// be allowed to retry.
+    pub fn forget_submission(&self, key: &str) {
+        self.submissions.lock().unwrap().remove(key);
+    }
+
+    /// Register a new stream for this session, unless the session already has `max` open
+    /// streams. The stream is unregistered when the returned guard is dropped.
+    pub fn try_start_stream(self: &Arc<Self>, max: NonZeroUsize) -> Option<StreamGuard<T>> {
+        self.streams
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                (x < max.get()).then_some(x + 1)
+            })
+            .ok()?;
+
+        Some(StreamGuard {
+            session: Arc::clone(self),
+        })
+    }
+}
+
+/// Unregisters a stream from its session when dropped.
+#[derive(Debug)]
+pub struct StreamGuard<T> {
+    session: Arc<Session<T>>,
+}
+
+impl<T> Drop for StreamGuard<T> {
+    fn drop(&mut self) {
+        self.session.streams.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Eq)]
 pub struct SessionSecret(u128);
 
+impl PartialEq for SessionSecret {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0.to_ne_bytes(), &other.0.to_ne_bytes())
+    }
+}
+
+// must agree with the `PartialEq` impl
+impl std::hash::Hash for SessionSecret {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
 impl SessionSecret {
     pub fn new(val: u128) -> Self {
         Self(val)
@@ -63,6 +255,54 @@ impl std::fmt::Debug for SessionSecret {
     }
 }
 
+/// A token that scripts can use to authenticate API requests with an `Authorization: Bearer`
+/// header rather than a session cookie. Each token belongs to a session.
+#[derive(Copy, Clone, Eq)]
+pub struct ApiToken(u128);
+
+impl PartialEq for ApiToken {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0.to_ne_bytes(), &other.0.to_ne_bytes())
+    }
+}
+
+// must agree with the `PartialEq` impl
+impl std::hash::Hash for ApiToken {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl ApiToken {
+    /// The number of hex digits in the token's string form.
+    const LEN: usize = 32;
+}
+
+impl std::fmt::Display for ApiToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:0len$x}", self.0, len = Self::LEN)
+    }
+}
+
+impl std::fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<token>")
+    }
+}
+
+impl std::str::FromStr for ApiToken {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `from_str_radix` would also accept a leading '+'
+        if s.len() != Self::LEN || !s.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(());
+        }
+
+        u128::from_str_radix(s, 16).map(Self).or(Err(()))
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct SessionCookieDisplay {
     secret: u128,
@@ -83,15 +323,19 @@ impl std::fmt::Display for SessionCookieDisplay {
             None => unreachable!(),
         };
 
-        // pad the secret to a constant length
-        write!(f, "{secret:0U128_MAX_DIGITS$}; HttpOnly; SameSite=Lax;")?;
+        // pad the secret to a constant length; the cookie is for the whole site and has no
+        // `Domain`, so it's compatible with the `__Host-` prefix if `Secure` is set
+        write!(
+            f,
+            "{secret:0U128_MAX_DIGITS$}; Path=/; HttpOnly; SameSite=Lax"
+        )?;
 
         if *secure_attr {
-            write!(f, " Secure;")?;
+            write!(f, "; Secure")?;
         }
 
         if let Some(expire) = expire {
-            write!(f, " Max-Age={};", expire.as_secs())?;
+            write!(f, "; Max-Age={}", expire.as_secs())?;
         }
 
         Ok(())
@@ -101,6 +345,8 @@ impl std::fmt::Display for SessionCookieDisplay {
 #[derive(Debug)]
 pub struct SessionManager<T> {
     sessions: RwLock<HashMap<SessionSecret, Arc<Session<T>>>>,
+    /// The session that each API token belongs to.
+    tokens: RwLock<HashMap<ApiToken, SessionSecret>>,
 }
 
 impl<T> SessionManager<T> {
@@ -136,8 +382,92 @@ impl<T> SessionManager<T> {
         session_secret
     }
 
+    /// Remove the session and expire it, so that anything still holding the session (such as an
+    /// open SSE stream) stops using it.
     pub fn remove_session(&self, secret: SessionSecret) -> Option<Arc<Session<T>>> {
-        self.sessions.write().unwrap().remove(&secret)
+        self.tokens.write().unwrap().retain(|_, x| *x != secret);
+        let session = self.sessions.write().unwrap().remove(&secret)?;
+        session.expire();
+        Some(session)
+    }
+
+    /// Remove the session with the given id (see `Session::id()`).
+    pub fn remove_session_by_id(&self, id: u64) -> Option<Arc<Session<T>>> {
+        let secret = self
+            .sessions
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, session)| session.id() == id)
+            .map(|(secret, _)| *secret)?;
+
+        self.remove_session(secret)
+    }
+
+    /// All sessions that haven't expired. The session secrets aren't included, so sessions can
+    /// only be identified by their ids.
+    pub fn sessions(&self) -> impl Iterator<Item = Arc<Session<T>>> {
+        let sessions: Vec<_> = self
+            .sessions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|session| !session.expired())
+            .cloned()
+            .collect();
+
+        sessions.into_iter()
+    }
+
+    /// Remove all expired sessions. Sessions are removed when they're next accessed after they
+    /// expire, but this should be called periodically to remove sessions that are never accessed
+    /// again.
+    pub fn remove_expired_sessions(&self) {
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, session| !session.expired());
+
+        self.tokens
+            .write()
+            .unwrap()
+            .retain(|_, secret| sessions.contains_key(secret));
+    }
+
+    /// Add to the unread completions of every session.
+    pub fn add_unread_completions(&self, count: usize) {
+        for session in self.sessions.read().unwrap().values() {
+            session.add_unread_completions(count);
+        }
+    }
+
+    /// Create a new API token for the session, replacing any previous token for the session.
+    /// Returns `None` if there is no such session.
+    pub fn new_token(&self, secret: SessionSecret) -> Option<ApiToken> {
+        // the session must exist (and not be expired)
+        self.session(secret)?;
+
+        let mut rng = rand::thread_rng();
+
+        let mut tokens = self.tokens.write().unwrap();
+
+        // each session has at most one token, so repeated requests can't grow the map
+        tokens.retain(|_, x| *x != secret);
+
+        let token = loop {
+            let x = ApiToken(rng.gen());
+            if !tokens.contains_key(&x) {
+                break x;
+            }
+        };
+
+        tokens.insert(token, secret);
+
+        Some(token)
+    }
+
+    /// The session that the API token belongs to.
+    pub fn session_from_token(&self, token: ApiToken) -> Option<Arc<Session<T>>> {
+        let secret = *self.tokens.read().unwrap().get(&token)?;
+        self.session(secret)
     }
 }
 
@@ -145,6 +475,115 @@ impl<T> Default for SessionManager<T> {
     fn default() -> Self {
         Self {
             sessions: Default::default(),
+            tokens: Default::default(),
+        }
+    }
+}
+
+/// Whether the two byte strings are equal, taking the same amount of time regardless of where
+/// they differ. Secrets (session secrets, tokens, passwords) should be compared with this so that
+/// the comparison doesn't leak how much of a guess was correct. Only the lengths may be leaked.
+#[inline(never)]
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+
+    // prevent the compiler from turning this back into an early-exit comparison
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"Secret"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"s"));
+
+        assert!(SessionSecret::new(5) == SessionSecret::new(5));
+        assert!(SessionSecret::new(5) != SessionSecret::new(5 << 64));
+        assert!(ApiToken(u128::MAX) == ApiToken(u128::MAX));
+        assert!(ApiToken(u128::MAX) != ApiToken(u128::MAX - 1));
+    }
+
+    #[test]
+    fn test_remove_session_by_id() {
+        let sessions = SessionManager::default();
+        let secret = sessions.new_session(Session::new(()));
+        let session = sessions.session(secret).unwrap();
+        assert!(!session.expired());
+
+        assert!(sessions.remove_session_by_id(session.id() + 1).is_none());
+        assert!(!session.expired());
+
+        // open streams hold their own reference, so the revoked session must also be expired
+        assert!(sessions.remove_session_by_id(session.id()).is_some());
+        assert!(session.expired());
+        assert!(sessions.session(secret).is_none());
+    }
+
+    #[test]
+    fn test_new_token() {
+        let sessions = SessionManager::default();
+        let secret = sessions.new_session(Session::new(()));
+        let other = sessions.new_session(Session::new(()));
+
+        let first = sessions.new_token(secret).unwrap();
+        let other_token = sessions.new_token(other).unwrap();
+        assert!(sessions.session_from_token(first).is_some());
+
+        // a new token replaces the session's previous token
+        let second = sessions.new_token(secret).unwrap();
+        assert!(sessions.session_from_token(first).is_none());
+        assert!(sessions.session_from_token(second).is_some());
+        assert!(sessions.session_from_token(other_token).is_some());
+        assert_eq!(sessions.tokens.read().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_session_cookie_display() {
+        let cookie = SessionSecret::new(12345).as_cookie(true, Some(Duration::from_secs(60)));
+        assert_eq!(
+            format!("session_secret={cookie}"),
+            "session_secret=000000000000000000000000000000000012345; Path=/; HttpOnly; \
+             SameSite=Lax; Secure; Max-Age=60",
+        );
+
+        let cookie = SessionSecret::new(1).as_cookie(false, None);
+        assert_eq!(
+            cookie.to_string(),
+            "000000000000000000000000000000000000001; Path=/; HttpOnly; SameSite=Lax",
+        );
+
+        // the secret is always padded to the length of the largest secret, and can be parsed back
+        for secret in [0, 1, 10, u128::MAX / 2, u128::MAX] {
+            let header = format!(
+                "session_secret={}",
+                SessionSecret::new(secret).as_cookie(true, None)
+            );
+            let cookie = cookie::Cookie::parse(header.as_str()).unwrap();
+
+            assert_eq!(cookie.name(), "session_secret");
+            assert_eq!(cookie.value().len(), u128::MAX.to_string().len());
+            assert_eq!(cookie.value().parse::<u128>().unwrap(), secret);
+            assert_eq!(cookie.path(), Some("/"));
+            assert_eq!(cookie.domain(), None);
+            assert_eq!(cookie.secure(), Some(true));
+            assert_eq!(cookie.http_only(), Some(true));
+            assert_eq!(cookie.same_site(), Some(cookie::SameSite::Lax));
+            assert_eq!(cookie.max_age(), None);
+
+            // no empty attributes, which some parsers reject
+            assert!(!header.ends_with(';'));
+            assert!(!header.contains(";;"));
         }
     }
 }
@@ -20,3 +20,126 @@ pub fn json_num_to_bool(val: &serde_json::Value) -> Option<bool> {
 pub fn identity_copy<T: Copy>(x: &T) -> T {
     *x
 }
+
+/// The torrent's value for `key`, or null if the field is missing (for example if it wasn't
+/// requested, or transmission is too old to support it). Templates can then use the `as_*`
+/// methods and fall back to a placeholder instead of panicking.
+pub fn get_or_empty(
+    torrent: &crate::transmission::types::Torrent,
+    key: crate::transmission::types::TorrentGetKey,
+) -> &serde_json::Value {
+    static EMPTY: serde_json::Value = serde_json::Value::Null;
+    torrent.get(&key).unwrap_or(&EMPTY)
+}
+
+/// Join a torrent's download directory and name into the path of the torrent's data. Transmission
+/// doesn't add a trailing slash to the download directory, but the user may have configured one.
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// The first few characters of a torrent's info hash, which is enough to tell torrents apart. The
+/// full hash should be available to copy using a "copy-full" button.
+pub fn truncated_hash(hash: &str) -> &str {
+    hash.get(..8).unwrap_or(hash)
+}
+
+/// A version of `name` that's safe to use as a filename in a `Content-Disposition` header. Returns
+/// `fallback` if there's nothing left of the name.
+pub fn safe_filename(name: &str, fallback: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_matches(|c| c == '.' || c == ' ');
+
+    if name.is_empty() {
+        fallback.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// The percentage of the torrent's wanted data that is available from ourselves and connected
+/// peers.
+///
+/// Newer versions of Transmission return "availability" as an array with the number of peers that
+/// have each piece (or -1 if we have the piece). Some return it as a single ratio instead, and
+/// older versions don't return it at all, in which case we fall back to "desiredAvailable".
+pub fn swarm_availability(torrent: &crate::transmission::types::Torrent) -> Option<f64> {
+    use crate::transmission::types::TorrentGetKey;
+
+    match torrent.get(&TorrentGetKey::Availability) {
+        Some(serde_json::Value::Array(pieces)) if !pieces.is_empty() => {
+            let available = pieces
+                .iter()
+                .filter(|x| x.as_i64().is_some_and(|x| x != 0))
+                .count();
+            return Some(available as f64 / pieces.len() as f64 * 100.0);
+        }
+        Some(serde_json::Value::Number(ratio)) => {
+            return ratio.as_f64().map(|x| x.clamp(0.0, 1.0) * 100.0);
+        }
+        _ => {}
+    }
+
+    let desired_available = torrent.get(&TorrentGetKey::DesiredAvailable)?.as_u64()?;
+    let left_until_done = torrent.get(&TorrentGetKey::LeftUntilDone)?.as_u64()?;
+    let size_when_done = torrent.size_when_done()?;
+
+    if size_when_done == 0 {
+        return None;
+    }
+
+    let have = size_when_done.saturating_sub(left_until_done);
+    let available = (have + desired_available).min(size_when_done);
+    Some(available as f64 / size_when_done as f64 * 100.0)
+}
+
+/// A human-readable bandwidth priority.
+pub fn priority_ui(priority: &i64) -> &'static str {
+    match *priority {
+        ..=-1 => "Low",
+        0 => "Normal",
+        1.. => "High",
+    }
+}
+
+/// A short human-readable duration using the two largest units, such as "2h 5m" or "45s".
+pub fn duration(secs: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+
+    let Some(first) = UNITS.iter().position(|(size, _)| secs >= *size) else {
+        return "0s".to_string();
+    };
+
+    let (size, unit) = UNITS[first];
+    let mut rv = format!("{}{unit}", secs / size);
+
+    if let Some((next_size, next_unit)) = UNITS.get(first + 1) {
+        let rem = (secs % size) / next_size;
+        if rem != 0 {
+            rv.push_str(&format!(" {rem}{next_unit}"));
+        }
+    }
+
+    rv
+}
+
+/// The time until a seeding torrent stops due to its idle limit. Transmission uses -1 if the
+/// torrent doesn't have an idle limit (or isn't seeding) and -2 if the time is unknown, in which
+/// case this returns `None`.
+pub fn idle_eta(torrent: &crate::transmission::types::Torrent) -> Option<String> {
+    let eta = u64::try_from(torrent.eta_idle()?).ok()?;
+    Some(duration(eta))
+}
@@ -0,0 +1,149 @@
+//! A mock transmission RPC server for tests.
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config;
+use crate::transmission::rpc::{AuthStrategy, RpcLimiter, TransmissionAuth, TransmissionRpc};
+
+/// The session ID that the mock server expects in the "X-Transmission-Session-Id" header.
+pub const SESSION_ID: &str = "mock-session-id";
+
+/// The path that the mock server accepts RPC requests at.
+pub const RPC_PATH: &str = "/transmission/rpc";
+
+type Handler = dyn Fn(&serde_json::Value) -> Response + Send + Sync;
+
+/// A mock transmission server listening on localhost. Like transmission, it responds with a 409
+/// status and the session ID if a request doesn't have the correct session ID. Other requests are
+/// passed to the handler. The server stops when the tokio runtime shuts down.
+pub struct MockTransmission {
+    url: config::RpcUrl,
+    state: Arc<MockState>,
+}
+
+struct MockState {
+    handler: Box<Handler>,
+    /// The number of requests received, including requests with the wrong session ID.
+    requests: AtomicUsize,
+    /// The body of each request that had the correct session ID.
+    bodies: Mutex<Vec<serde_json::Value>>,
+    /// The headers of each request that had the correct session ID.
+    headers: Mutex<Vec<HeaderMap>>,
+}
+
+impl MockTransmission {
+    /// Start a server that responds to each RPC request (which has the correct session ID) with
+    /// the response from `handler`. The handler is given the JSON request.
+    pub async fn start(
+        handler: impl Fn(&serde_json::Value) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let state = Arc::new(MockState {
+            handler: Box::new(handler),
+            requests: AtomicUsize::new(0),
+            bodies: Mutex::new(Vec::new()),
+            headers: Mutex::new(Vec::new()),
+        });
+
+        let app = Router::new()
+            .route(RPC_PATH, post(rpc_post))
+            .with_state(Arc::clone(&state));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Self {
+            url: config::RpcUrl::new(format!("http://{addr}"), RPC_PATH.to_string()),
+            state,
+        }
+    }
+
+    /// Start a server that responds to every RPC request with a successful result and the given
+    /// arguments.
+    pub async fn with_arguments(arguments: serde_json::Value) -> Self {
+        Self::start(move |_req| success(arguments.clone())).await
+    }
+
+    pub fn url(&self) -> config::RpcUrl {
+        self.url.clone()
+    }
+
+    /// The number of requests received, including requests with the wrong session ID.
+    pub fn requests(&self) -> usize {
+        self.state.requests.load(Ordering::SeqCst)
+    }
+
+    /// The body of each request that had the correct session ID.
+    pub fn bodies(&self) -> Vec<serde_json::Value> {
+        self.state.bodies.lock().unwrap().clone()
+    }
+
+    /// The headers of each request that had the correct session ID.
+    pub fn headers(&self) -> Vec<HeaderMap> {
+        self.state.headers.lock().unwrap().clone()
+    }
+}
+
+async fn rpc_post(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    state.requests.fetch_add(1, Ordering::SeqCst);
+
+    let id = headers
+        .get("X-Transmission-Session-Id")
+        .and_then(|x| x.to_str().ok());
+    if id != Some(SESSION_ID) {
+        return (
+            StatusCode::CONFLICT,
+            [("X-Transmission-Session-Id", SESSION_ID)],
+            "<h1>409: Conflict</h1>",
+        )
+            .into_response();
+    }
+
+    let resp = (state.handler)(&body);
+    state.bodies.lock().unwrap().push(body);
+    state.headers.lock().unwrap().push(headers);
+    resp
+}
+
+/// An RPC client for `url` that uses basic auth with test credentials.
+pub fn test_rpc(url: config::RpcUrl) -> TransmissionRpc {
+    test_rpc_with_auth(url, AuthStrategy::default())
+}
+
+/// An RPC client for `url` that sends test credentials using `auth_strategy`.
+pub fn test_rpc_with_auth(url: config::RpcUrl, auth_strategy: AuthStrategy) -> TransmissionRpc {
+    let auth = TransmissionAuth {
+        username: "user".to_string(),
+        password: "pass".to_string(),
+    };
+    let limiter = RpcLimiter::new(NonZeroUsize::new(4).unwrap(), Duration::from_secs(5));
+    TransmissionRpc::new(url, auth, auth_strategy, None, Arc::new(limiter))
+}
+
+/// A successful RPC response with the given arguments.
+pub fn success(arguments: serde_json::Value) -> Response {
+    json(&serde_json::json!({"result": "success", "arguments": arguments}).to_string())
+}
+
+/// A response with a JSON content type, like transmission's responses. The body doesn't need to
+/// be valid JSON.
+pub fn json(body: &str) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=UTF-8")],
+        body.to_string(),
+    )
+        .into_response()
+}
@@ -1,2 +1,4 @@
+#[cfg(test)]
+pub mod mock;
 pub mod rpc;
 pub mod types;
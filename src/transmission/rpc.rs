@@ -1,100 +1,257 @@
+use axum::body::Bytes;
 use axum::http::StatusCode;
-use tokio::sync::Notify;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::{Notify, OnceCell, Semaphore, SemaphorePermit};
 
-use std::sync::RwLock;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
 
 use crate::config;
+use crate::session;
 use crate::transmission;
 
+tokio::task_local! {
+    /// The total time spent on transmission requests while handling the current http request, or
+    /// `None` if no transmission requests were made. This is only tracked within
+    /// `RPC_DURATION.scope()`.
+    pub static RPC_DURATION: Cell<Option<Duration>>;
+}
+
 #[derive(Debug)]
 pub struct TransmissionRpc {
     url: config::RpcUrl,
     auth: TransmissionAuth,
+    auth_strategy: AuthStrategy,
     /// The transmission session ID. Will need to be updated infrequently.
     id: RwLock<String>,
+    /// The transmission version, fetched once when first needed.
+    version: OnceLock<transmission::types::TransmissionVersion>,
+    /// The units that transmission displays sizes with, fetched once when first needed.
+    units: OnceLock<transmission::types::Units>,
+    /// Read-only requests that are currently in-flight, keyed by the serialized request. Identical
+    /// requests made at the same time will share a single response.
+    in_flight: Mutex<HashMap<String, Arc<InFlightResponse>>>,
+    /// Shared by all sessions.
+    limiter: Arc<RpcLimiter>,
     pub notify: Notify,
 }
 
 impl TransmissionRpc {
-    pub fn new(url: config::RpcUrl, auth: TransmissionAuth) -> Self {
+    pub fn new(
+        url: config::RpcUrl,
+        auth: TransmissionAuth,
+        auth_strategy: AuthStrategy,
+        id: Option<String>,
+        limiter: Arc<RpcLimiter>,
+    ) -> Self {
         Self {
             url,
             auth,
-            id: RwLock::new(String::new()),
+            auth_strategy,
+            // if we don't know the ID, transmission will give us one in its first response
+            id: RwLock::new(id.unwrap_or_default()),
+            version: OnceLock::new(),
+            units: OnceLock::new(),
+            in_flight: Mutex::new(HashMap::new()),
+            limiter,
             notify: Notify::new(),
         }
     }
 
-    pub async fn request<T: serde::de::DeserializeOwned>(
+    pub fn auth(&self) -> &TransmissionAuth {
+        &self.auth
+    }
+
+    /// The version of the transmission server. This is only requested from transmission the first
+    /// time it's needed, and is cached after that.
+    pub async fn version(
         &self,
         rpc: &reqwest::Client,
-        msg: &transmission::types::Request,
-    ) -> Result<transmission::types::Response<T>, StatusCode> {
-        let resp = self.csrf_request(rpc, msg).await?;
+    ) -> Result<&transmission::types::TransmissionVersion, RpcError> {
+        if let Some(version) = self.version.get() {
+            return Ok(version);
+        }
 
-        match resp.status() {
-            x @ reqwest::StatusCode::UNAUTHORIZED => {
-                // could be wrong username/password
-                return Err(x);
-            }
-            x @ reqwest::StatusCode::FORBIDDEN => {
-                // could be connecting from a non-whitelisted IP
-                return Err(x);
-            }
-            x if !x.is_success() => {
+        let request = transmission::types::Request::session_get(
+            transmission::types::TransmissionVersion::KEYS.to_vec(),
+        );
+        let resp = self
+            .request::<transmission::types::SessionGetResponse>(rpc, &request)
+            .await?;
+
+        let version = transmission::types::TransmissionVersion::from_response(&resp.arguments)
+            .ok_or_else(|| {
                 println!(
-                    "Transmission returned {}: {}",
-                    resp.status(),
-                    resp.text().await.unwrap_or(String::new()),
+                    "Transmission returned an invalid version: {:?}",
+                    resp.arguments
                 );
-                return Err(StatusCode::BAD_GATEWAY);
-            }
-            _ => {}
+                RpcError::BadGateway
+            })?;
+
+        // if another task set the version at the same time, we'll use theirs instead
+        Ok(self.version.get_or_init(|| version))
+    }
+
+    /// The units that the transmission server uses for sizes. This is only requested from
+    /// transmission the first time it's needed. Binary units are used if transmission doesn't
+    /// return any units.
+    pub async fn units(
+        &self,
+        rpc: &reqwest::Client,
+    ) -> Result<&transmission::types::Units, RpcError> {
+        if let Some(units) = self.units.get() {
+            return Ok(units);
         }
 
-        // transmission unfortunately uses success http statuses for unsucessful rpc requests
+        let request =
+            transmission::types::Request::session_get(transmission::types::Units::KEYS.to_vec());
+        let resp = self
+            .request::<transmission::types::SessionGetResponse>(rpc, &request)
+            .await?;
+
+        let units = transmission::types::Units::from_response(&resp.arguments).unwrap_or_default();
+
+        // if another task set the units at the same time, we'll use theirs instead
+        Ok(self.units.get_or_init(|| units))
+    }
+
+    /// The RPC version of the transmission server.
+    pub async fn rpc_version(&self, rpc: &reqwest::Client) -> Result<u64, RpcError> {
+        Ok(self.version(rpc).await?.rpc_version)
+    }
 
-        let resp = resp
-            .json::<transmission::types::Response<T>>()
+    /// Returns an error response with an explanation if the transmission server is too old to
+    /// support `feature`. Handlers should call this before making requests that use the feature,
+    /// since older transmission versions return unhelpful errors (or silently ignore fields).
+    pub async fn require(
+        &self,
+        rpc: &reqwest::Client,
+        feature: transmission::types::RpcFeature,
+    ) -> Result<(), Response> {
+        let rpc_version = self
+            .rpc_version(rpc)
             .await
-            .inspect_err(|e| println!("Failed to parse JSON response: {e:?}"))
-            .or(Err(StatusCode::BAD_GATEWAY))?;
+            .map_err(IntoResponse::into_response)?;
 
-        if !resp.is_success() {
-            println!(
-                "Transmission returned an unsuccessful response: {}",
-                resp.result,
+        if rpc_version < feature.min_rpc_version() {
+            let msg = format!(
+                "{} requires a newer version of Transmission (RPC version {} or later, but the server has RPC version {rpc_version})",
+                feature.ui(),
+                feature.min_rpc_version(),
             );
-            return Err(StatusCode::BAD_GATEWAY);
+            return Err((StatusCode::NOT_IMPLEMENTED, msg).into_response());
         }
 
-        Ok(resp)
+        Ok(())
     }
 
-    async fn csrf_request<T: serde::Serialize + ?Sized>(
+    pub async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        rpc: &reqwest::Client,
+        msg: &transmission::types::Request,
+    ) -> Result<transmission::types::Response<T>, RpcError> {
+        let start = Instant::now();
+
+        let resp = if msg.request.is_read_only() {
+            self.coalesced_request(rpc, msg).await
+        } else {
+            self.raw_request(rpc, msg).await
+        };
+
+        // ignore the error if we're not within a scope
+        let _ = RPC_DURATION.try_with(|x| {
+            x.set(Some(x.get().unwrap_or_default() + start.elapsed()));
+        });
+
+        resp?.parse()
+    }
+
+    /// Make the request, or if an identical request is already in-flight, wait for and use its
+    /// response instead. This should only be used for requests that don't modify anything.
+    async fn coalesced_request<T: serde::Serialize + ?Sized>(
         &self,
         rpc: &reqwest::Client,
         msg: &T,
-    ) -> Result<reqwest::Response, StatusCode> {
-        let old_id: String = self
-            .id
-            .read()
-            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+    ) -> Result<RawResponse, RpcError> {
+        let key = serde_json::to_string(msg).or(Err(RpcError::Internal))?;
+
+        let cell = Arc::clone(
+            self.in_flight
+                .lock()
+                .or(Err(RpcError::Internal))?
+                .entry(key.clone())
+                .or_default(),
+        );
+
+        // if the task making the request is cancelled, one of the waiting tasks will make the
+        // request instead
+        let resp = cell
+            .get_or_init(|| self.raw_request(rpc, msg))
+            .await
             .clone();
 
+        // the request is no longer in-flight, so remove it (unless a new request has already
+        // replaced it)
+        let mut in_flight = self.in_flight.lock().or(Err(RpcError::Internal))?;
+        if in_flight.get(&key).is_some_and(|x| Arc::ptr_eq(x, &cell)) {
+            in_flight.remove(&key);
+        }
+
+        resp
+    }
+
+    /// Make the request and read the full response body.
+    async fn raw_request<T: serde::Serialize + ?Sized>(
+        &self,
+        rpc: &reqwest::Client,
+        msg: &T,
+    ) -> Result<RawResponse, RpcError> {
+        // held until the body has been read
+        let _permit = self.limiter.acquire().await?;
+
+        let resp = self.csrf_request(rpc, msg).await?;
+
+        let status = resp.status();
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .map(|x| String::from_utf8_lossy(x.as_bytes()).into_owned());
+        let body = resp
+            .bytes()
+            .await
+            .inspect_err(|e| println!("Failed to read response body: {e:?}"))
+            .map_err(|e| RpcError::from_reqwest(&e))?;
+
+        Ok(RawResponse {
+            status,
+            content_type,
+            body,
+        })
+    }
+
+    async fn csrf_request<T: serde::Serialize + ?Sized>(
+        &self,
+        rpc: &reqwest::Client,
+        msg: &T,
+    ) -> Result<reqwest::Response, RpcError> {
+        let old_id: String = self.id.read().or(Err(RpcError::Internal))?.clone();
+
         let resp = self.http_request(rpc, &old_id, msg).await?;
 
         if let Some(new_id) = resp.headers().get("X-Transmission-Session-Id") {
             let new_id = new_id
                 .to_str()
                 .inspect_err(|e| println!("Bad transmission session ID: {e:?}"))
-                .or(Err(StatusCode::BAD_GATEWAY))?
+                .or(Err(RpcError::BadGateway))?
                 .to_string();
 
             if new_id != old_id {
                 self.id
                     .write()
-                    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+                    .or(Err(RpcError::Internal))?
                     .clone_from(&new_id);
             }
 
@@ -111,20 +268,702 @@ impl TransmissionRpc {
         rpc: &reqwest::Client,
         rpc_id: &str,
         msg: &T,
-    ) -> Result<reqwest::Response, StatusCode> {
-        rpc.post(&self.url.to_string())
-            .basic_auth(&self.auth.username, Some(&self.auth.password))
+    ) -> Result<reqwest::Response, RpcError> {
+        let req = rpc
+            .post(self.url.to_string())
             .header("X-Transmission-Session-Id", rpc_id)
-            .json(msg)
+            .json(msg);
+
+        self.auth_strategy
+            .apply(req, &self.auth)
             .send()
             .await
             .inspect_err(|e| println!("Sending json request failed: {e:?}"))
-            .or(Err(StatusCode::BAD_GATEWAY))
+            .map_err(|e| RpcError::from_reqwest(&e))
     }
 }
 
+/// Wait until transmission's RPC server responds, or until the timeout expires. Any HTTP response
+/// (even an authentication or session ID error) means that transmission is running, so no
+/// credentials are needed. Returns whether transmission responded.
+pub async fn wait_until_reachable(
+    rpc: &reqwest::Client,
+    url: &config::RpcUrl,
+    timeout: Duration,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+
+        let resp = rpc
+            .post(url.to_string())
+            .timeout(remaining.min(Duration::from_secs(5)))
+            .send()
+            .await;
+
+        match resp {
+            Ok(_) => return true,
+            Err(e) => println!(
+                "Waiting for transmission at {}: {}",
+                url.redacted(),
+                e.without_url(),
+            ),
+        }
+
+        tokio::time::sleep_until(
+            deadline.min(tokio::time::Instant::now() + Duration::from_secs(1)),
+        )
+        .await;
+    }
+}
+
+/// The result of checking whether transmission's RPC server can be reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnosis {
+    /// Transmission responded, and doesn't require credentials.
+    Reachable,
+    /// Transmission responded, and requires credentials (which the user gives when logging in).
+    AuthRequired,
+    /// Transmission rejected the request, probably because of its RPC whitelist.
+    Forbidden,
+    /// Something responded, but not at the RPC path.
+    NotFound,
+    /// Something responded with an unexpected HTTP status.
+    UnexpectedStatus(u16),
+    /// The hostname couldn't be resolved.
+    DnsFailure,
+    /// Nothing is listening at the address.
+    ConnectionRefused,
+    /// The connection or response timed out.
+    Timeout,
+    /// Some other error. Contains the error message.
+    Other(String),
+}
+
+impl Diagnosis {
+    /// Make a request to transmission without credentials and classify the response.
+    pub async fn check(rpc: &reqwest::Client, url: &config::RpcUrl) -> Self {
+        let resp = rpc
+            .post(url.to_string())
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await;
+
+        match resp {
+            Ok(resp) => Self::from_status(resp.status()),
+            // the url may contain credentials, and the message may be shown to the user
+            Err(e) => Self::from_error(&e.without_url()),
+        }
+    }
+
+    pub fn from_status(status: reqwest::StatusCode) -> Self {
+        match status {
+            // transmission asks for a session id before doing anything else
+            reqwest::StatusCode::CONFLICT => Self::Reachable,
+            reqwest::StatusCode::UNAUTHORIZED => Self::AuthRequired,
+            reqwest::StatusCode::FORBIDDEN => Self::Forbidden,
+            reqwest::StatusCode::NOT_FOUND => Self::NotFound,
+            x if x.is_success() => Self::Reachable,
+            x => Self::UnexpectedStatus(x.as_u16()),
+        }
+    }
+
+    pub fn from_error(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return Self::Timeout;
+        }
+
+        // reqwest doesn't expose the cause of connection errors, so look through the error chain
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+        while let Some(x) = source {
+            if let Some(io) = x.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused => return Self::ConnectionRefused,
+                    std::io::ErrorKind::TimedOut => return Self::Timeout,
+                    _ => {}
+                }
+            }
+            // hyper's resolver errors are only identifiable by their message
+            if x.to_string().starts_with("dns error") {
+                return Self::DnsFailure;
+            }
+            source = x.source();
+        }
+
+        Self::Other(e.to_string())
+    }
+
+    /// Whether transmission is running at the URL, even if we aren't authorized to use it.
+    pub fn is_reachable(&self) -> bool {
+        matches!(self, Self::Reachable | Self::AuthRequired)
+    }
+
+    /// A description of the problem (if any) and how the user might fix it.
+    pub fn guidance(&self) -> String {
+        match self {
+            Self::Reachable => "Transmission is reachable".to_string(),
+            Self::AuthRequired => "Transmission is reachable and requires a username and password, which are given on the login page".to_string(),
+            Self::Forbidden => r#"Transmission rejected the connection; check that transportal's IP address is in Transmission's "rpc-whitelist" setting"#.to_string(),
+            Self::NotFound => r#"No RPC server was found at this path; check the "rpc_url_path" option (usually "/transmission/rpc")"#.to_string(),
+            Self::UnexpectedStatus(x) => format!(r#"Unexpected HTTP status {x}; check that the "rpc_url_base" and "rpc_url_path" options point to Transmission"#),
+            Self::DnsFailure => r#"The hostname couldn't be resolved; check the "rpc_url_base" option"#.to_string(),
+            Self::ConnectionRefused => r#"The connection was refused; check that Transmission is running and that the port in "rpc_url_base" is correct"#.to_string(),
+            Self::Timeout => "The connection timed out; check that Transmission is running and not blocked by a firewall".to_string(),
+            Self::Other(e) => format!("Failed to connect: {e}"),
+        }
+    }
+}
+
+/// Whether the content type is JSON, such as "application/json; charset=UTF-8".
+fn is_json(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.eq_ignore_ascii_case("application/json") || essence.ends_with("+json")
+}
+
+/// Limits the number of requests made to transmission at the same time across all sessions, so
+/// that many SSE connections don't overwhelm a small transmission server.
+#[derive(Debug)]
+pub struct RpcLimiter {
+    semaphore: Semaphore,
+    max: usize,
+    /// How long to wait for another request to finish before giving up.
+    timeout: Duration,
+}
+
+impl RpcLimiter {
+    pub fn new(max: NonZeroUsize, timeout: Duration) -> Self {
+        Self {
+            semaphore: Semaphore::new(max.get()),
+            max: max.get(),
+            timeout,
+        }
+    }
+
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, RpcError> {
+        match tokio::time::timeout(self.timeout, self.semaphore.acquire()).await {
+            Ok(permit) => permit.or(Err(RpcError::Internal)),
+            Err(_) => {
+                println!("Timed out waiting for other requests to transmission to finish");
+                Err(RpcError::Busy)
+            }
+        }
+    }
+
+    /// The number of requests to transmission that are currently in-flight.
+    pub fn in_flight(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+}
+
+type InFlightResponse = OnceCell<Result<RawResponse, RpcError>>;
+
+/// A response from transmission. The body has already been read so that the response can be
+/// shared between coalesced requests.
 #[derive(Debug, Clone)]
+struct RawResponse {
+    status: reqwest::StatusCode,
+    /// The "Content-Type" header, if any.
+    content_type: Option<String>,
+    body: Bytes,
+}
+
+impl RawResponse {
+    /// Map the HTTP status and RPC result to an error, or parse the response.
+    fn parse<T: serde::de::DeserializeOwned>(
+        &self,
+    ) -> Result<transmission::types::Response<T>, RpcError> {
+        match self.status {
+            reqwest::StatusCode::UNAUTHORIZED => {
+                // could be wrong username/password
+                return Err(RpcError::Unauthorized);
+            }
+            reqwest::StatusCode::FORBIDDEN => {
+                // could be connecting from a non-whitelisted IP
+                return Err(RpcError::Forbidden);
+            }
+            _ => {}
+        }
+
+        // if the rpc url is wrong we might get an html page (for example from transmission's web
+        // ui or a reverse proxy), so give a more useful error than a json parse error
+        if let Some(content_type) = self.content_type.as_ref().filter(|x| !is_json(x)) {
+            if self.status.is_success() || self.status == reqwest::StatusCode::NOT_FOUND {
+                println!(
+                    "Transmission returned {} with content type {content_type:?} instead of JSON",
+                    self.status,
+                );
+                return Err(RpcError::NotJson(content_type.clone()));
+            }
+        }
+
+        match self.status {
+            x if !x.is_success() => {
+                println!(
+                    "Transmission returned {}: {}",
+                    self.status,
+                    String::from_utf8_lossy(&self.body),
+                );
+                return Err(RpcError::BadGateway);
+            }
+            _ => {}
+        }
+
+        // transmission unfortunately uses success http statuses for unsucessful rpc requests
+
+        let resp = serde_json::from_slice::<transmission::types::Response<T>>(&self.body)
+            .inspect_err(|e| println!("Failed to parse JSON response: {e:?}"))
+            .or(Err(RpcError::BadGateway))?;
+
+        if !resp.is_success() {
+            println!(
+                "Transmission returned an unsuccessful response: {}",
+                resp.result,
+            );
+            return Err(RpcError::Unsuccessful(resp.result));
+        }
+
+        Ok(resp)
+    }
+}
+
+/// An error when making a request to transmission.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// Transmission rejected the credentials.
+    Unauthorized,
+    /// Transmission rejected the request, for example if transportal isn't connecting from a
+    /// whitelisted IP.
+    Forbidden,
+    /// Transmission processed the request but returned an unsuccessful result. Contains
+    /// transmission's description of the error, for example "invalid argument".
+    Unsuccessful(String),
+    /// Transmission's response wasn't JSON, which probably means that the RPC URL is
+    /// misconfigured. Contains the response's content type.
+    NotJson(String),
+    /// Too many requests to transmission were already in-flight.
+    Busy,
+    /// Transmission couldn't be reached, for example if it isn't running.
+    Unreachable,
+    /// Transmission was reached but didn't respond within the `rpc_timeout_secs` timeout.
+    Timeout,
+    /// There was some other problem communicating with transmission.
+    BadGateway,
+    /// There was an internal error.
+    Internal,
+}
+
+impl RpcError {
+    /// Classify an error from sending a request or reading its response. Only connection errors
+    /// (including connection timeouts) mean that transmission is unavailable, since a slow
+    /// response means that it's running.
+    fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_connect() {
+            Self::Unreachable
+        } else if e.is_timeout() {
+            Self::Timeout
+        } else {
+            Self::BadGateway
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Unsuccessful(_) => StatusCode::BAD_GATEWAY,
+            Self::NotJson(_) => StatusCode::BAD_GATEWAY,
+            Self::BadGateway => StatusCode::BAD_GATEWAY,
+            Self::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            // a separate status from other transmission errors, so that the unavailable page and
+            // SSE streams can tell when transmission is down even after the error is converted to
+            // a status code
+            Self::Unreachable => StatusCode::GATEWAY_TIMEOUT,
+            Self::Timeout => StatusCode::BAD_GATEWAY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "Not authorized"),
+            Self::Forbidden => write!(f, "Forbidden"),
+            Self::Unsuccessful(result) => write!(f, "Transmission returned an error: {result}"),
+            Self::NotJson(content_type) => write!(
+                f,
+                "RPC URL may be misconfigured (got {content_type} instead of JSON)",
+            ),
+            Self::BadGateway => write!(f, "Failed to communicate with Transmission"),
+            Self::Busy => write!(f, "Too many requests to Transmission"),
+            Self::Unreachable => write!(f, "Transmission is unavailable"),
+            Self::Timeout => write!(f, "Transmission took too long to respond"),
+            Self::Internal => write!(f, "Internal error"),
+        }
+    }
+}
+
+impl From<RpcError> for StatusCode {
+    fn from(e: RpcError) -> Self {
+        e.status()
+    }
+}
+
+impl IntoResponse for RpcError {
+    fn into_response(self) -> Response {
+        match self {
+            // only include a body when we have something useful to say, since other middleware
+            // (such as the unauthorized redirect) expect some errors to have empty bodies
+            Self::Unsuccessful(_) | Self::NotJson(_) | Self::Timeout => {
+                (self.status(), self.to_string()).into_response()
+            }
+            _ => self.status().into_response(),
+        }
+    }
+}
+
+#[derive(Clone, Eq)]
 pub struct TransmissionAuth {
     pub username: String,
     pub password: String,
 }
+
+impl std::fmt::Debug for TransmissionAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // sessions (and their credentials) may be debug-printed, so never show the password
+        f.debug_struct("TransmissionAuth")
+            .field("username", &self.username)
+            .field("password", &"<secret>")
+            .finish()
+    }
+}
+
+impl PartialEq for TransmissionAuth {
+    fn eq(&self, other: &Self) -> bool {
+        // don't short-circuit so that the time doesn't depend on which of them differed
+        let username =
+            session::constant_time_eq(self.username.as_bytes(), other.username.as_bytes());
+        let password =
+            session::constant_time_eq(self.password.as_bytes(), other.password.as_bytes());
+        username & password
+    }
+}
+
+/// How the credentials are sent with each request. Transmission itself only supports basic auth,
+/// but proxies in front of it may expect a token instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthStrategy {
+    /// HTTP basic auth with the username and password.
+    #[default]
+    Basic,
+    /// An `Authorization: Bearer` header with the password as the token.
+    Bearer,
+    /// The password as the value of the given header.
+    Header(reqwest::header::HeaderName),
+}
+
+impl AuthStrategy {
+    pub fn from_config(config: &config::ConfigConnection) -> anyhow::Result<Self> {
+        if config.rpc_auth != config::RpcAuthMethod::Header && config.rpc_auth_header.is_some() {
+            anyhow::bail!(r#""rpc_auth_header" can only be set when "rpc_auth" is "header""#);
+        }
+
+        Ok(match config.rpc_auth {
+            config::RpcAuthMethod::Basic => Self::Basic,
+            config::RpcAuthMethod::Bearer => Self::Bearer,
+            config::RpcAuthMethod::Header => {
+                let name = config.rpc_auth_header.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!(r#""rpc_auth_header" must be set when "rpc_auth" is "header""#)
+                })?;
+                Self::Header(reqwest::header::HeaderName::from_bytes(name.as_bytes())?)
+            }
+        })
+    }
+
+    fn apply(
+        &self,
+        req: reqwest::RequestBuilder,
+        auth: &TransmissionAuth,
+    ) -> reqwest::RequestBuilder {
+        match self {
+            Self::Basic => req.basic_auth(&auth.username, Some(&auth.password)),
+            Self::Bearer => req.bearer_auth(&auth.password),
+            Self::Header(name) => req.header(name, &auth.password),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::response::Html;
+    use transmission::mock::{self, MockTransmission};
+    use transmission::types::{Request, TorrentGetFormat, TorrentGetKey, TorrentGetResponse};
+
+    fn rpc(mock: &MockTransmission) -> TransmissionRpc {
+        mock::test_rpc(mock.url())
+    }
+
+    #[test]
+    fn test_auth_debug() {
+        let auth = TransmissionAuth {
+            username: "user".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let debug = format!("{auth:?}");
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_request_session_id_handshake() {
+        let mock = MockTransmission::with_arguments(serde_json::json!({
+            "torrents": [{"hashString": "abc", "name": "Ubuntu"}],
+        }))
+        .await;
+        let rpc = rpc(&mock);
+        let client = reqwest::Client::new();
+
+        let request = Request::torrent_get(
+            TorrentGetFormat::Objects,
+            vec![TorrentGetKey::HashString, TorrentGetKey::Name],
+            None,
+        );
+
+        // the first request is rejected since we don't know the session ID yet, so it's retried
+        let resp = rpc
+            .request::<TorrentGetResponse>(&client, &request)
+            .await
+            .unwrap();
+        assert_eq!(mock.requests(), 2);
+
+        let torrents = &resp.arguments.torrents;
+        assert_eq!(torrents.len(), 1);
+        assert_eq!(torrents[0].name(), Some("Ubuntu"));
+        assert_eq!(torrents[0].hash(), Some("abc"));
+
+        // the session ID is remembered for later requests
+        rpc.request::<TorrentGetResponse>(&client, &request)
+            .await
+            .unwrap();
+        assert_eq!(mock.requests(), 3);
+
+        let bodies = mock.bodies();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["method"], "torrent-get");
+        assert_eq!(
+            bodies[0]["arguments"]["fields"],
+            serde_json::json!(["hashString", "name"]),
+        );
+    }
+
+    /// Make a "torrent-get" request to a mock server that responds with `handler`.
+    async fn request_with(
+        handler: impl Fn(&serde_json::Value) -> Response + Send + Sync + 'static,
+    ) -> (MockTransmission, Result<(), RpcError>) {
+        let mock = MockTransmission::start(handler).await;
+        let request =
+            Request::torrent_get(TorrentGetFormat::Objects, vec![TorrentGetKey::Id], None);
+        let resp = rpc(&mock)
+            .request::<TorrentGetResponse>(&reqwest::Client::new(), &request)
+            .await
+            .map(|_| ());
+        (mock, resp)
+    }
+
+    #[tokio::test]
+    async fn test_request_unreachable() {
+        // find a port that nothing is listening on
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let url = config::RpcUrl::new(format!("http://{addr}"), "/transmission/rpc".to_string());
+        let rpc = mock::test_rpc(url);
+
+        let request =
+            Request::torrent_get(TorrentGetFormat::Objects, vec![TorrentGetKey::Id], None);
+        let resp = rpc
+            .request::<TorrentGetResponse>(&reqwest::Client::new(), &request)
+            .await;
+        assert_eq!(resp.unwrap_err(), RpcError::Unreachable);
+        assert_eq!(RpcError::Unreachable.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        // accept connections but never respond, like a busy transmission
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let url = config::RpcUrl::new(format!("http://{addr}"), "/transmission/rpc".to_string());
+        let rpc = mock::test_rpc(url);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let request =
+            Request::torrent_get(TorrentGetFormat::Objects, vec![TorrentGetKey::Id], None);
+        let resp = rpc.request::<TorrentGetResponse>(&client, &request).await;
+
+        // a slow transmission isn't reported as unavailable
+        assert_eq!(resp.unwrap_err(), RpcError::Timeout);
+        assert_eq!(RpcError::Timeout.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_request_conflict_retried_once() {
+        // a 409 after the retry (for example if transmission restarted) isn't retried again
+        let handler = |_: &_| {
+            let id = [("X-Transmission-Session-Id", "new-id")];
+            (StatusCode::CONFLICT, id).into_response()
+        };
+        let (mock, resp) = request_with(handler).await;
+        assert_eq!(resp, Err(RpcError::BadGateway));
+        assert_eq!(mock.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_request_missing_session_id() {
+        let (mock, resp) = request_with(|_| StatusCode::CONFLICT.into_response()).await;
+        assert_eq!(resp, Err(RpcError::BadGateway));
+        assert_eq!(mock.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_request_status_errors() {
+        let (_mock, resp) = request_with(|_| StatusCode::UNAUTHORIZED.into_response()).await;
+        assert_eq!(resp, Err(RpcError::Unauthorized));
+
+        let (_mock, resp) = request_with(|_| StatusCode::FORBIDDEN.into_response()).await;
+        assert_eq!(resp, Err(RpcError::Forbidden));
+
+        let (_mock, resp) =
+            request_with(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response()).await;
+        assert_eq!(resp, Err(RpcError::BadGateway));
+    }
+
+    #[tokio::test]
+    async fn test_request_bad_body() {
+        let (_mock, resp) = request_with(|_| mock::json("<h1>Not JSON</h1>")).await;
+        assert_eq!(resp, Err(RpcError::BadGateway));
+
+        // the response is missing the "arguments"
+        let (_mock, resp) = request_with(|_| mock::json(r#"{"result": "success"}"#)).await;
+        assert_eq!(resp, Err(RpcError::BadGateway));
+    }
+
+    #[tokio::test]
+    async fn test_request_html() {
+        let html = "text/html; charset=utf-8".to_string();
+
+        let (_mock, resp) = request_with(|_| Html("<h1>Web UI</h1>").into_response()).await;
+        assert_eq!(resp, Err(RpcError::NotJson(html.clone())));
+
+        let handler = |_: &_| (StatusCode::NOT_FOUND, Html("<h1>Not Found</h1>")).into_response();
+        let (_mock, resp) = request_with(handler).await;
+        assert_eq!(resp, Err(RpcError::NotJson(html)));
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json("application/json"));
+        assert!(is_json("application/json; charset=UTF-8"));
+        assert!(is_json("Application/JSON"));
+        assert!(is_json("application/problem+json"));
+        assert!(!is_json("text/html"));
+        assert!(!is_json("text/plain; charset=utf-8"));
+        assert!(!is_json(""));
+    }
+
+    #[tokio::test]
+    async fn test_request_unsuccessful_result() {
+        let handler =
+            |_: &_| mock::json(r#"{"result": "invalid argument", "arguments": {"torrents": []}}"#);
+        let (_mock, resp) = request_with(handler).await;
+        assert_eq!(
+            resp,
+            Err(RpcError::Unsuccessful("invalid argument".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_auth_strategy_from_config() {
+        let from_config = |extra: &str| {
+            let config = config::tests::config_with(extra).unwrap();
+            AuthStrategy::from_config(&config.connection)
+        };
+
+        assert_eq!(from_config("").unwrap(), AuthStrategy::Basic);
+        assert_eq!(
+            from_config(r#"rpc_auth = "bearer""#).unwrap(),
+            AuthStrategy::Bearer
+        );
+        assert_eq!(
+            from_config("rpc_auth = \"header\"\nrpc_auth_header = \"X-Api-Key\"").unwrap(),
+            AuthStrategy::Header(reqwest::header::HeaderName::from_static("x-api-key")),
+        );
+
+        assert!(from_config(r#"rpc_auth = "header""#).is_err());
+        assert!(from_config(r#"rpc_auth_header = "X-Api-Key""#).is_err());
+        assert!(from_config("rpc_auth = \"bearer\"\nrpc_auth_header = \"X-Api-Key\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_auth_strategy() {
+        let strategies = [
+            (AuthStrategy::Basic, "authorization", "Basic dXNlcjpwYXNz"),
+            (AuthStrategy::Bearer, "authorization", "Bearer pass"),
+            (
+                AuthStrategy::Header("x-api-key".parse().unwrap()),
+                "x-api-key",
+                "pass",
+            ),
+        ];
+
+        for (strategy, header, expected) in strategies {
+            let mock = MockTransmission::with_arguments(serde_json::json!({"torrents": []})).await;
+            let rpc = mock::test_rpc_with_auth(mock.url(), strategy.clone());
+            let client = reqwest::Client::new();
+
+            let request = Request::torrent_get(TorrentGetFormat::Objects, vec![], None);
+            let resp = rpc.request::<TorrentGetResponse>(&client, &request).await;
+            assert!(resp.is_ok());
+
+            let headers = mock.headers();
+            assert_eq!(headers.len(), 1);
+            assert_eq!(headers[0][header], expected, "{strategy:?}");
+
+            // the credentials should only be sent once
+            if header != "authorization" {
+                assert!(!headers[0].contains_key("authorization"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limiter() {
+        let limiter = RpcLimiter::new(NonZeroUsize::new(1).unwrap(), Duration::from_millis(10));
+        assert_eq!(limiter.in_flight(), 0);
+
+        let permit = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.in_flight(), 1);
+        assert_eq!(limiter.acquire().await.err(), Some(RpcError::Busy));
+
+        drop(permit);
+        assert_eq!(limiter.in_flight(), 0);
+        assert!(limiter.acquire().await.is_ok());
+    }
+}
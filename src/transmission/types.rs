@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 #[derive(Clone, Debug, Serialize)]
 pub struct Request {
@@ -16,6 +17,41 @@ impl Request {
         Self { request, tag: None }
     }
 
+    pub fn session_set(args: SessionSetArgs) -> Self {
+        let request = RequestInner::SessionSet { args };
+        Self { request, tag: None }
+    }
+
+    pub fn blocklist_update() -> Self {
+        let request = RequestInner::BlocklistUpdate;
+        Self { request, tag: None }
+    }
+
+    pub fn port_test() -> Self {
+        let request = RequestInner::PortTest;
+        Self { request, tag: None }
+    }
+
+    /// Tell transmission to shut down.
+    pub fn session_close() -> Self {
+        let request = RequestInner::SessionClose;
+        Self { request, tag: None }
+    }
+
+    /// Get the bandwidth groups with the given names, or all groups if `None`. Requires
+    /// `RpcFeature::BandwidthGroups`.
+    pub fn group_get(names: Option<Vec<String>>) -> Self {
+        let request = RequestInner::GroupGet { group: names };
+        Self { request, tag: None }
+    }
+
+    /// Create the bandwidth group, or update it if a group with the same name exists. Requires
+    /// `RpcFeature::BandwidthGroups`.
+    pub fn group_set(group: BandwidthGroup) -> Self {
+        let request = RequestInner::GroupSet { group };
+        Self { request, tag: None }
+    }
+
     pub fn torrent_get(
         format: TorrentGetFormat,
         keys: Vec<TorrentGetKey>,
@@ -23,7 +59,18 @@ impl Request {
     ) -> Self {
         let request = RequestInner::TorrentGet {
             format,
-            ids,
+            ids: ids.map(TorrentIds::List),
+            fields: keys,
+        };
+        Self { request, tag: None }
+    }
+
+    /// Like `torrent_get`, but only returns the torrents that were active recently. The response
+    /// also lists the ids of torrents that were removed recently.
+    pub fn torrent_get_recently_active(format: TorrentGetFormat, keys: Vec<TorrentGetKey>) -> Self {
+        let request = RequestInner::TorrentGet {
+            format,
+            ids: Some(TorrentIds::RecentlyActive),
             fields: keys,
         };
         Self { request, tag: None }
@@ -44,11 +91,56 @@ impl Request {
         Self { request, tag: None }
     }
 
-    pub fn torrent_add(required: TorrentAddRequired, paused: bool) -> Self {
+    pub fn torrent_set(ids: Option<Vec<String>>, args: TorrentSetArgs) -> Self {
+        let request = RequestInner::TorrentSet { ids, args };
+        Self { request, tag: None }
+    }
+
+    pub fn torrent_remove(ids: Option<Vec<String>>, delete_local_data: bool) -> Self {
+        let request = RequestInner::TorrentRemove {
+            ids,
+            delete_local_data: Some(delete_local_data),
+        };
+        Self { request, tag: None }
+    }
+
+    /// Move the torrents one position up in the queue.
+    pub fn queue_move_up(ids: Option<Vec<String>>) -> Self {
+        let request = RequestInner::QueueMoveUp { ids };
+        Self { request, tag: None }
+    }
+
+    /// Move the torrents one position down in the queue.
+    pub fn queue_move_down(ids: Option<Vec<String>>) -> Self {
+        let request = RequestInner::QueueMoveDown { ids };
+        Self { request, tag: None }
+    }
+
+    /// Move the torrents to a new location. If `move_data` is false, transmission will look for
+    /// the existing data in the new location instead of moving it.
+    pub fn torrent_set_location(
+        ids: Option<Vec<String>>,
+        location: String,
+        move_data: bool,
+    ) -> Self {
+        let request = RequestInner::TorrentSetLocation {
+            ids,
+            location,
+            move_data,
+        };
+        Self { request, tag: None }
+    }
+
+    pub fn torrent_add(
+        required: TorrentAddRequired,
+        paused: bool,
+        download_dir: Option<String>,
+        cookies: Option<String>,
+    ) -> Self {
         let request = RequestInner::TorrentAdd {
             required,
-            cookies: None,
-            download_dir: None,
+            cookies,
+            download_dir,
             labels: None,
             paused: Some(paused),
             peer_limit: None,
@@ -68,6 +160,29 @@ impl Request {
     }
 }
 
+impl RequestInner {
+    /// Whether the request only reads data and doesn't modify anything.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Self::SessionGet { .. } | Self::TorrentGet { .. } | Self::GroupGet { .. } => true,
+            Self::TorrentStart { .. }
+            | Self::TorrentStop { .. }
+            | Self::TorrentVerify { .. }
+            | Self::TorrentRemove { .. }
+            | Self::TorrentSetLocation { .. }
+            | Self::QueueMoveUp { .. }
+            | Self::QueueMoveDown { .. }
+            | Self::TorrentSet { .. }
+            | Self::TorrentAdd { .. }
+            | Self::SessionSet { .. }
+            | Self::GroupSet { .. }
+            | Self::BlocklistUpdate
+            | Self::PortTest
+            | Self::SessionClose => false,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "method", content = "arguments")]
@@ -75,11 +190,26 @@ pub enum RequestInner {
     SessionGet {
         fields: Vec<SessionGetKey>,
     },
+    SessionSet {
+        #[serde(flatten)]
+        args: SessionSetArgs,
+    },
+    BlocklistUpdate,
+    PortTest,
+    SessionClose,
+    GroupGet {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group: Option<Vec<String>>,
+    },
+    GroupSet {
+        #[serde(flatten)]
+        group: BandwidthGroup,
+    },
     TorrentGet {
         format: TorrentGetFormat,
         fields: Vec<TorrentGetKey>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        ids: Option<Vec<String>>,
+        ids: Option<TorrentIds>,
     },
     TorrentStart {
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -93,6 +223,34 @@ pub enum RequestInner {
         #[serde(skip_serializing_if = "Option::is_none")]
         ids: Option<Vec<String>>,
     },
+    TorrentSet {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
+        #[serde(flatten)]
+        args: TorrentSetArgs,
+    },
+    TorrentRemove {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "delete-local-data")]
+        delete_local_data: Option<bool>,
+    },
+    TorrentSetLocation {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
+        location: String,
+        #[serde(rename = "move")]
+        move_data: bool,
+    },
+    QueueMoveUp {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
+    },
+    QueueMoveDown {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
+    },
     TorrentAdd {
         #[serde(flatten)]
         required: TorrentAddRequired,
@@ -122,6 +280,285 @@ pub enum RequestInner {
     },
 }
 
+/// A bandwidth group, which limits the combined speed of the torrents assigned to it. Used in both
+/// "group-get" responses and "group-set" requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BandwidthGroup {
+    pub name: String,
+    #[serde(rename = "honorsSessionLimits")]
+    pub honors_session_limits: bool,
+    pub speed_limit_down_enabled: bool,
+    /// In KB/s.
+    pub speed_limit_down: u64,
+    pub speed_limit_up_enabled: bool,
+    /// In KB/s.
+    pub speed_limit_up: u64,
+}
+
+/// The torrents that a "torrent-get" request applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TorrentIds {
+    /// Torrent ids or hashes.
+    List(Vec<String>),
+    /// The torrents that were active in the last minute.
+    RecentlyActive,
+}
+
+impl Serialize for TorrentIds {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::List(ids) => ids.serialize(serializer),
+            Self::RecentlyActive => serializer.serialize_str("recently-active"),
+        }
+    }
+}
+
+/// A builder for the fields to change with a "torrent-set" request. Only the fields that were set
+/// are sent, so a request won't clobber fields that the caller didn't intend to change.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TorrentSetArgs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bandwidth_priority: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_limited: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    honors_session_limits: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed_idle_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed_idle_mode: Option<IdleLimitMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequential_download: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_limited: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "files-wanted")]
+    files_wanted: Option<Vec<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "files-unwanted")]
+    files_unwanted: Option<Vec<usize>>,
+}
+
+impl TorrentSetArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the request wouldn't change anything.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// -1 (low), 0 (normal), or 1 (high).
+    pub fn bandwidth_priority(mut self, x: i64) -> Self {
+        self.bandwidth_priority = Some(x);
+        self
+    }
+
+    /// In KB/s.
+    pub fn download_limit(mut self, x: u64) -> Self {
+        self.download_limit = Some(x);
+        self
+    }
+
+    pub fn download_limited(mut self, x: bool) -> Self {
+        self.download_limited = Some(x);
+        self
+    }
+
+    pub fn honors_session_limits(mut self, x: bool) -> Self {
+        self.honors_session_limits = Some(x);
+        self
+    }
+
+    /// Requires `RpcFeature::BandwidthGroups`. An empty string removes the torrent from its group.
+    pub fn group(mut self, x: String) -> Self {
+        self.group = Some(x);
+        self
+    }
+
+    pub fn labels(mut self, x: Vec<String>) -> Self {
+        self.labels = Some(x);
+        self
+    }
+
+    /// In minutes. Only used if the idle mode is `IdleLimitMode::Single`.
+    pub fn seed_idle_limit(mut self, x: u64) -> Self {
+        self.seed_idle_limit = Some(x);
+        self
+    }
+
+    pub fn seed_idle_mode(mut self, x: IdleLimitMode) -> Self {
+        self.seed_idle_mode = Some(x);
+        self
+    }
+
+    /// Requires `RpcFeature::SequentialDownload`.
+    pub fn sequential_download(mut self, x: bool) -> Self {
+        self.sequential_download = Some(x);
+        self
+    }
+
+    /// In KB/s.
+    pub fn upload_limit(mut self, x: u64) -> Self {
+        self.upload_limit = Some(x);
+        self
+    }
+
+    pub fn upload_limited(mut self, x: bool) -> Self {
+        self.upload_limited = Some(x);
+        self
+    }
+
+    /// Indices of files to download. An empty list is ignored.
+    pub fn files_wanted(mut self, x: Vec<usize>) -> Self {
+        self.files_wanted = Some(x).filter(|x| !x.is_empty());
+        self
+    }
+
+    /// Indices of files to not download. An empty list is ignored.
+    pub fn files_unwanted(mut self, x: Vec<usize>) -> Self {
+        self.files_unwanted = Some(x).filter(|x| !x.is_empty());
+        self
+    }
+}
+
+/// Whether a torrent stops seeding after being idle for some time ("seedIdleMode").
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleLimitMode {
+    /// Use the session's idle limit.
+    #[allow(dead_code)]
+    Global = 0,
+    /// Use the torrent's own idle limit.
+    Single = 1,
+    /// Never stop seeding due to being idle.
+    #[allow(dead_code)]
+    Unlimited = 2,
+}
+
+impl Serialize for IdleLimitMode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+/// Whether peer connections are encrypted ("encryption").
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Only connect to peers that use encryption.
+    Required,
+    /// Prefer encrypted connections, but allow unencrypted ones.
+    Preferred,
+    /// Prefer unencrypted connections, but allow encrypted ones.
+    Tolerated,
+}
+
+impl Encryption {
+    pub const ALL: &'static [Self] = &[Self::Required, Self::Preferred, Self::Tolerated];
+
+    /// The value used by transmission.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Required => "required",
+            Self::Preferred => "preferred",
+            Self::Tolerated => "tolerated",
+        }
+    }
+
+    pub fn ui(&self) -> &'static str {
+        match self {
+            Self::Required => "Required",
+            Self::Preferred => "Preferred",
+            Self::Tolerated => "Allowed",
+        }
+    }
+}
+
+impl std::str::FromStr for Encryption {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|x| x.as_str() == s)
+            .copied()
+            .ok_or(())
+    }
+}
+
+/// A builder for the fields to change with a "session-set" request. Only the fields that were set
+/// are sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionSetArgs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocklist_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocklist_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dht_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lpd_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pex_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utp_enabled: Option<bool>,
+}
+
+impl SessionSetArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn blocklist_enabled(mut self, x: bool) -> Self {
+        self.blocklist_enabled = Some(x);
+        self
+    }
+
+    pub fn blocklist_url(mut self, x: String) -> Self {
+        self.blocklist_url = Some(x);
+        self
+    }
+
+    pub fn dht_enabled(mut self, x: bool) -> Self {
+        self.dht_enabled = Some(x);
+        self
+    }
+
+    pub fn encryption(mut self, x: Encryption) -> Self {
+        self.encryption = Some(x);
+        self
+    }
+
+    pub fn lpd_enabled(mut self, x: bool) -> Self {
+        self.lpd_enabled = Some(x);
+        self
+    }
+
+    pub fn pex_enabled(mut self, x: bool) -> Self {
+        self.pex_enabled = Some(x);
+        self
+    }
+
+    pub fn utp_enabled(mut self, x: bool) -> Self {
+        self.utp_enabled = Some(x);
+        self
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum TorrentAddRequired {
@@ -287,6 +724,55 @@ pub enum TorrentGetKey {
     WebseedsSendingToUs,
 }
 
+impl TorrentGetKey {
+    /// Whether transmission can change the field on its own while a torrent is active, rather than
+    /// only when a user changes the torrent. `Name` and `SizeWhenDone` also change when a magnet
+    /// link's metadata is downloaded, which `MetadataPercentComplete` can be used to detect.
+    pub fn changes_often(&self) -> bool {
+        matches!(
+            self,
+            Self::ActivityDate
+                | Self::Availability
+                | Self::CorruptEver
+                | Self::DesiredAvailable
+                | Self::DoneDate
+                | Self::DownloadedEver
+                | Self::Error
+                | Self::ErrorString
+                | Self::Eta
+                | Self::EtaIdle
+                | Self::FileStats
+                | Self::HaveUnchecked
+                | Self::HaveValid
+                | Self::IsFinished
+                | Self::IsStalled
+                | Self::LeftUntilDone
+                | Self::ManualAnnounceTime
+                | Self::MetadataPercentComplete
+                | Self::Peers
+                | Self::PeersConnected
+                | Self::PeersFrom
+                | Self::PeersGettingFromUs
+                | Self::PeersSendingToUs
+                | Self::PercentComplete
+                | Self::PercentDone
+                | Self::Pieces
+                | Self::QueuePosition
+                | Self::RateDownload
+                | Self::RateUpload
+                | Self::RecheckProgress
+                | Self::SecondsDownloading
+                | Self::SecondsSeeding
+                | Self::StartDate
+                | Self::Status
+                | Self::TrackerStats
+                | Self::UploadedEver
+                | Self::UploadRatio
+                | Self::WebseedsSendingToUs
+        )
+    }
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub enum TorrentStatus {
     /// Torrent is stopped.
@@ -356,10 +842,54 @@ impl std::fmt::Display for TorrentStatus {
     }
 }
 
+/// A reason that a torrent may need the user's attention.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum AttentionReason {
+    /// The torrent has a tracker or local error.
+    Error,
+    /// The torrent hasn't transferred any data recently.
+    Stalled,
+    /// The torrent is downloading but isn't connected to any peers.
+    NoPeers,
+}
+
+impl AttentionReason {
+    /// Get the reasons that the torrent needs attention. The torrent should have the `Error`,
+    /// `IsStalled`, `PeersConnected`, and `Status` keys, otherwise those reasons are ignored.
+    pub fn from_torrent(torrent: &Torrent) -> Vec<Self> {
+        let mut reasons = Vec::new();
+
+        if torrent.error().is_some_and(|x| x != 0) {
+            reasons.push(Self::Error);
+        }
+
+        if torrent.is_stalled() == Some(true) {
+            reasons.push(Self::Stalled);
+        }
+
+        if torrent.status() == Some(TorrentStatus::Downloading)
+            && torrent.peers_connected() == Some(0)
+        {
+            reasons.push(Self::NoPeers);
+        }
+
+        reasons
+    }
+
+    pub fn ui(&self) -> &'static str {
+        match self {
+            Self::Error => "Error",
+            Self::Stalled => "Stalled",
+            Self::NoPeers => "No peers",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Response<T> {
     pub result: String,
     pub arguments: T,
+    #[allow(dead_code)]
     pub tag: Option<u32>,
 }
 
@@ -372,9 +902,331 @@ impl<T> Response<T> {
 #[derive(Clone, Debug, Deserialize)]
 pub struct SessionGetResponse(pub HashMap<SessionGetKey, serde_json::Value>);
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct GroupGetResponse {
+    pub group: Vec<BandwidthGroup>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BlocklistUpdateResponse {
+    /// The number of rules in the updated blocklist.
+    pub blocklist_size: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PortTestResponse {
+    pub port_is_open: bool,
+}
+
+/// The version information of a Transmission server.
+#[derive(Clone, Debug, Serialize)]
+pub struct TransmissionVersion {
+    /// The Transmission release version. Ex: `4.0.5 (a6fe2a64aa)`.
+    pub version: String,
+    /// The RPC API version.
+    pub rpc_version: u64,
+    /// The RPC API version in semver format. Only available in Transmission 4 and later.
+    pub rpc_version_semver: Option<String>,
+}
+
+impl TransmissionVersion {
+    /// The keys needed to build a `TransmissionVersion` from a `session-get` response.
+    pub const KEYS: [SessionGetKey; 3] = [
+        SessionGetKey::Version,
+        SessionGetKey::RpcVersion,
+        SessionGetKey::RpcVersionSemver,
+    ];
+
+    pub fn from_response(resp: &SessionGetResponse) -> Option<Self> {
+        let version = resp.0.get(&SessionGetKey::Version)?.as_str()?.to_string();
+        let rpc_version = resp.0.get(&SessionGetKey::RpcVersion)?.as_u64()?;
+        let rpc_version_semver = resp
+            .0
+            .get(&SessionGetKey::RpcVersionSemver)
+            .and_then(|x| x.as_str())
+            .map(ToString::to_string);
+
+        Some(Self {
+            version,
+            rpc_version,
+            rpc_version_semver,
+        })
+    }
+
+    pub fn supports(&self, feature: RpcFeature) -> bool {
+        self.rpc_version >= feature.min_rpc_version()
+    }
+}
+
+/// The units that a Transmission server uses to display sizes, so that we can show the same numbers
+/// as Transmission's own clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Units {
+    /// The number of bytes in the smallest size unit (1000 for SI units or 1024 for binary units).
+    pub size_bytes: u64,
+    /// The names of the size units, from smallest to largest. Ex: `["kB", "MB", "GB", "TB"]`.
+    pub size_units: Vec<String>,
+}
+
+impl Units {
+    /// The keys needed to build a `Units` from a `session-get` response.
+    pub const KEYS: [SessionGetKey; 1] = [SessionGetKey::Units];
+
+    pub fn from_response(resp: &SessionGetResponse) -> Option<Self> {
+        let units = resp.0.get(&SessionGetKey::Units)?;
+        let size_bytes = units.get("size-bytes")?.as_u64()?;
+        let size_units = units
+            .get("size-units")?
+            .as_array()?
+            .iter()
+            .map(|x| x.as_str().map(ToString::to_string))
+            .collect::<Option<Vec<_>>>()?;
+
+        if size_bytes < 2 || size_units.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            size_bytes,
+            size_units,
+        })
+    }
+
+    /// Binary units, used if Transmission doesn't tell us which units it uses.
+    pub fn binary() -> Self {
+        Self {
+            size_bytes: 1024,
+            size_units: ["KiB", "MiB", "GiB", "TiB"].map(String::from).to_vec(),
+        }
+    }
+
+    /// Format a number of bytes as a human-readable size. Ex: `1.5 GiB`.
+    pub fn size(&self, bytes: impl Borrow<u64>) -> String {
+        let bytes = *bytes.borrow();
+
+        if bytes < self.size_bytes {
+            return format!("{bytes} B");
+        }
+
+        let mut value = bytes as f64 / self.size_bytes as f64;
+        let mut unit = &self.size_units[0];
+        for next_unit in &self.size_units[1..] {
+            if value < self.size_bytes as f64 {
+                break;
+            }
+            value /= self.size_bytes as f64;
+            unit = next_unit;
+        }
+
+        format!("{value:.1} {unit}")
+    }
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self::binary()
+    }
+}
+
+/// Transmission features that aren't available in all supported Transmission versions.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RpcFeature {
+    /// The `group-get` and `group-set` methods, and the torrent `group` field.
+    BandwidthGroups,
+    /// The session `default-trackers` field.
+    DefaultTrackers,
+    /// The torrent `sequentialDownload` field.
+    SequentialDownload,
+}
+
+impl RpcFeature {
+    pub const ALL: [Self; 3] = [
+        Self::BandwidthGroups,
+        Self::DefaultTrackers,
+        Self::SequentialDownload,
+    ];
+
+    /// The first RPC version that supports this feature.
+    pub fn min_rpc_version(&self) -> u64 {
+        match self {
+            // transmission 4.0.0
+            Self::BandwidthGroups => 17,
+            Self::DefaultTrackers => 17,
+            // transmission 4.1.0
+            Self::SequentialDownload => 18,
+        }
+    }
+
+    pub fn ui(&self) -> &'static str {
+        match self {
+            Self::BandwidthGroups => "Bandwidth groups",
+            Self::DefaultTrackers => "Default trackers",
+            Self::SequentialDownload => "Sequential download",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct TorrentGetResponse {
-    pub torrents: Vec<BTreeMap<TorrentGetKey, serde_json::Value>>,
+    pub torrents: Vec<Torrent>,
+    /// The ids of torrents that were removed recently. Only returned for "recently-active"
+    /// requests.
+    #[serde(default)]
+    pub removed: Vec<u64>,
+}
+
+impl TorrentGetResponse {
+    /// The distinct download directories of the torrents. The response must have been requested
+    /// with the `DownloadDir` key.
+    pub fn download_dirs(&self) -> BTreeSet<&str> {
+        self.torrents
+            .iter()
+            .filter_map(Torrent::download_dir)
+            .collect()
+    }
+}
+
+/// A torrent object from a "torrent-get" response. Only the requested fields are present, so each
+/// accessor returns `None` if its field is missing or has an unexpected type. Fields that don't
+/// have an accessor can still be read from the raw fields with `get()`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Torrent(BTreeMap<TorrentGetKey, serde_json::Value>);
+
+impl Torrent {
+    /// The raw value of a field.
+    pub fn get(&self, key: &TorrentGetKey) -> Option<&serde_json::Value> {
+        self.0.get(key)
+    }
+
+    /// All of the raw fields.
+    pub fn fields(&self) -> &BTreeMap<TorrentGetKey, serde_json::Value> {
+        &self.0
+    }
+
+    pub fn fields_mut(&mut self) -> &mut BTreeMap<TorrentGetKey, serde_json::Value> {
+        &mut self.0
+    }
+
+    fn str(&self, key: TorrentGetKey) -> Option<&str> {
+        self.get(&key)?.as_str()
+    }
+
+    fn u64(&self, key: TorrentGetKey) -> Option<u64> {
+        self.get(&key)?.as_u64()
+    }
+
+    fn i64(&self, key: TorrentGetKey) -> Option<i64> {
+        self.get(&key)?.as_i64()
+    }
+
+    fn f64(&self, key: TorrentGetKey) -> Option<f64> {
+        self.get(&key)?.as_f64()
+    }
+
+    fn bool(&self, key: TorrentGetKey) -> Option<bool> {
+        self.get(&key)?.as_bool()
+    }
+
+    /// Transmission's id for the torrent, which isn't persistent across restarts.
+    pub fn id(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::Id)
+    }
+
+    /// The info hash.
+    pub fn hash(&self) -> Option<&str> {
+        self.str(TorrentGetKey::HashString)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.str(TorrentGetKey::Name)
+    }
+
+    pub fn magnet_link(&self) -> Option<&str> {
+        self.str(TorrentGetKey::MagnetLink)
+    }
+
+    pub fn status(&self) -> Option<TorrentStatus> {
+        TorrentStatus::try_from(self.u64(TorrentGetKey::Status)?).ok()
+    }
+
+    /// Transmission's error type, or 0 if there's no error.
+    pub fn error(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::Error)
+    }
+
+    pub fn error_string(&self) -> Option<&str> {
+        self.str(TorrentGetKey::ErrorString)
+    }
+
+    pub fn is_stalled(&self) -> Option<bool> {
+        self.bool(TorrentGetKey::IsStalled)
+    }
+
+    pub fn is_private(&self) -> Option<bool> {
+        self.bool(TorrentGetKey::IsPrivate)
+    }
+
+    pub fn peers_connected(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::PeersConnected)
+    }
+
+    /// Between 0 and 1.
+    pub fn percent_done(&self) -> Option<f64> {
+        self.f64(TorrentGetKey::PercentDone)
+    }
+
+    /// Between 0 and 1.
+    pub fn metadata_percent_complete(&self) -> Option<f64> {
+        self.f64(TorrentGetKey::MetadataPercentComplete)
+    }
+
+    /// In bytes.
+    pub fn size_when_done(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::SizeWhenDone)
+    }
+
+    /// A unix timestamp.
+    pub fn added_date(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::AddedDate)
+    }
+
+    /// In seconds. Negative if unknown or not applicable.
+    pub fn eta(&self) -> Option<i64> {
+        self.i64(TorrentGetKey::Eta)
+    }
+
+    /// In seconds. Negative if unknown or not applicable.
+    pub fn eta_idle(&self) -> Option<i64> {
+        self.i64(TorrentGetKey::EtaIdle)
+    }
+
+    pub fn download_dir(&self) -> Option<&str> {
+        self.str(TorrentGetKey::DownloadDir)
+    }
+
+    pub fn bandwidth_priority(&self) -> Option<i64> {
+        self.i64(TorrentGetKey::BandwidthPriority)
+    }
+
+    pub fn queue_position(&self) -> Option<u64> {
+        self.u64(TorrentGetKey::QueuePosition)
+    }
+
+    /// `None` if any label isn't a string.
+    pub fn labels(&self) -> Option<Vec<&str>> {
+        let labels = self.get(&TorrentGetKey::Labels)?.as_array()?;
+        labels.iter().map(|x| x.as_str()).collect()
+    }
+}
+
+impl From<BTreeMap<TorrentGetKey, serde_json::Value>> for Torrent {
+    fn from(fields: BTreeMap<TorrentGetKey, serde_json::Value>) -> Self {
+        Self(fields)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -390,6 +1242,21 @@ impl TorrentAddResponse {
             TorrentAddedOrDuplicate::TorrentDuplicate(x) => &x.hash_string,
         }
     }
+
+    pub fn name(&self) -> &str {
+        match &self.added_or_duplicate {
+            TorrentAddedOrDuplicate::TorrentAdded(x) => &x.name,
+            TorrentAddedOrDuplicate::TorrentDuplicate(x) => &x.name,
+        }
+    }
+
+    /// Whether the torrent had already been added to transmission.
+    pub fn is_duplicate(&self) -> bool {
+        matches!(
+            self.added_or_duplicate,
+            TorrentAddedOrDuplicate::TorrentDuplicate(_)
+        )
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -404,6 +1271,7 @@ pub enum TorrentAddedOrDuplicate {
 pub struct TorrentAdded {
     pub hash_string: String,
     pub name: String,
+    #[allow(dead_code)]
     pub id: u32,
 }
 
@@ -412,5 +1280,263 @@ pub struct TorrentAdded {
 pub struct TorrentDuplicate {
     pub hash_string: String,
     pub name: String,
+    #[allow(dead_code)]
     pub id: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        let units = Units::binary();
+        assert_eq!(units.size(0), "0 B");
+        assert_eq!(units.size(1023), "1023 B");
+        assert_eq!(units.size(1024), "1.0 KiB");
+        assert_eq!(units.size(1536 * 1024), "1.5 MiB");
+        assert_eq!(units.size(2048 * 1024u64.pow(4)), "2048.0 TiB");
+
+        let resp = SessionGetResponse(HashMap::from([(
+            SessionGetKey::Units,
+            serde_json::json!({
+                "size-bytes": 1000,
+                "size-units": ["kB", "MB", "GB", "TB"],
+                "speed-bytes": 1000,
+                "speed-units": ["kB/s", "MB/s", "GB/s", "TB/s"],
+            }),
+        )]));
+        let units = Units::from_response(&resp).unwrap();
+        assert_eq!(units.size(999), "999 B");
+        assert_eq!(units.size(1_500_000), "1.5 MB");
+
+        assert_eq!(
+            Units::from_response(&SessionGetResponse(HashMap::new())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_torrent_get_ids() {
+        let ids = |x: Request| serde_json::to_value(x).unwrap()["arguments"]["ids"].clone();
+        let format = || TorrentGetFormat::Objects;
+
+        assert_eq!(
+            ids(Request::torrent_get(format(), vec![], None)),
+            serde_json::Value::Null,
+        );
+        assert_eq!(
+            ids(Request::torrent_get(
+                format(),
+                vec![],
+                Some(vec!["abc".into()])
+            )),
+            serde_json::json!(["abc"]),
+        );
+        assert_eq!(
+            ids(Request::torrent_get_recently_active(format(), vec![])),
+            serde_json::json!("recently-active"),
+        );
+    }
+
+    #[test]
+    fn test_session_set_and_blocklist_update() {
+        let request = Request::session_set(
+            SessionSetArgs::new()
+                .blocklist_enabled(true)
+                .blocklist_url("https://example.com/list.gz".to_string()),
+        );
+        assert_eq!(
+            serde_json::to_value(request).unwrap(),
+            serde_json::json!({
+                "method": "session-set",
+                "arguments": {
+                    "blocklist-enabled": true,
+                    "blocklist-url": "https://example.com/list.gz",
+                },
+            }),
+        );
+
+        let request = Request::session_set(
+            SessionSetArgs::new()
+                .dht_enabled(false)
+                .pex_enabled(true)
+                .lpd_enabled(false)
+                .utp_enabled(true)
+                .encryption(Encryption::Required),
+        );
+        assert_eq!(
+            serde_json::to_value(request).unwrap()["arguments"],
+            serde_json::json!({
+                "dht-enabled": false,
+                "pex-enabled": true,
+                "lpd-enabled": false,
+                "utp-enabled": true,
+                "encryption": "required",
+            }),
+        );
+
+        for x in Encryption::ALL {
+            assert_eq!(x.as_str().parse(), Ok(*x));
+            assert_eq!(serde_json::to_value(x).unwrap(), x.as_str());
+        }
+        assert_eq!("Required".parse::<Encryption>(), Err(()));
+
+        let group = BandwidthGroup {
+            name: "slow".to_string(),
+            honors_session_limits: true,
+            speed_limit_down_enabled: true,
+            speed_limit_down: 100,
+            speed_limit_up_enabled: false,
+            speed_limit_up: 50,
+        };
+        let json = serde_json::json!({
+            "name": "slow",
+            "honorsSessionLimits": true,
+            "speed-limit-down-enabled": true,
+            "speed-limit-down": 100,
+            "speed-limit-up-enabled": false,
+            "speed-limit-up": 50,
+        });
+        assert_eq!(
+            serde_json::to_value(Request::group_set(group.clone())).unwrap(),
+            serde_json::json!({"method": "group-set", "arguments": json}),
+        );
+        let resp: GroupGetResponse =
+            serde_json::from_value(serde_json::json!({"group": [json]})).unwrap();
+        assert_eq!(resp.group, [group]);
+        assert_eq!(
+            serde_json::to_value(Request::group_get(None)).unwrap(),
+            serde_json::json!({"method": "group-get", "arguments": {}}),
+        );
+        assert_eq!(
+            serde_json::to_value(Request::group_get(Some(vec!["slow".to_string()]))).unwrap(),
+            serde_json::json!({"method": "group-get", "arguments": {"group": ["slow"]}}),
+        );
+
+        assert_eq!(
+            serde_json::to_value(Request::blocklist_update()).unwrap(),
+            serde_json::json!({"method": "blocklist-update"}),
+        );
+        assert_eq!(
+            serde_json::to_value(Request::session_close()).unwrap(),
+            serde_json::json!({"method": "session-close"}),
+        );
+        assert_eq!(
+            serde_json::to_value(Request::torrent_set_location(
+                Some(vec!["abc".to_string(), "def".to_string()]),
+                "/data".to_string(),
+                true,
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "method": "torrent-set-location",
+                "arguments": {"ids": ["abc", "def"], "location": "/data", "move": true},
+            }),
+        );
+        assert_eq!(
+            serde_json::to_value(Request::queue_move_up(Some(vec!["abc".to_string()]))).unwrap(),
+            serde_json::json!({"method": "queue-move-up", "arguments": {"ids": ["abc"]}}),
+        );
+        assert_eq!(
+            serde_json::to_value(Request::queue_move_down(None)).unwrap(),
+            serde_json::json!({"method": "queue-move-down", "arguments": {}}),
+        );
+    }
+
+    #[test]
+    fn test_torrent_set_omits_unset_fields() {
+        let args = |x: TorrentSetArgs| serde_json::to_value(x).unwrap();
+
+        assert_eq!(args(TorrentSetArgs::new()), serde_json::json!({}));
+        assert!(TorrentSetArgs::new().is_empty());
+
+        assert_eq!(
+            args(TorrentSetArgs::new().download_limit(100)),
+            serde_json::json!({"downloadLimit": 100}),
+        );
+        assert_eq!(
+            args(
+                TorrentSetArgs::new()
+                    .sequential_download(true)
+                    .download_limited(false)
+            ),
+            serde_json::json!({"sequentialDownload": true, "downloadLimited": false}),
+        );
+        assert_eq!(
+            args(TorrentSetArgs::new().labels(vec![])),
+            serde_json::json!({"labels": []}),
+        );
+        assert_eq!(
+            args(TorrentSetArgs::new().group(String::new())),
+            serde_json::json!({"group": ""}),
+        );
+        assert_eq!(
+            args(
+                TorrentSetArgs::new()
+                    .seed_idle_limit(30)
+                    .seed_idle_mode(IdleLimitMode::Single)
+            ),
+            serde_json::json!({"seedIdleLimit": 30, "seedIdleMode": 1}),
+        );
+
+        // empty file lists wouldn't change anything
+        assert_eq!(
+            args(
+                TorrentSetArgs::new()
+                    .files_wanted(vec![0, 2])
+                    .files_unwanted(vec![])
+            ),
+            serde_json::json!({"files-wanted": [0, 2]}),
+        );
+        assert!(TorrentSetArgs::new().files_unwanted(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_torrent_set_request() {
+        let request = Request::torrent_set(
+            Some(vec!["abc".to_string()]),
+            TorrentSetArgs::new().bandwidth_priority(1),
+        );
+
+        assert_eq!(
+            serde_json::to_value(request).unwrap(),
+            serde_json::json!({
+                "method": "torrent-set",
+                "arguments": {"ids": ["abc"], "bandwidthPriority": 1},
+            }),
+        );
+    }
+
+    #[test]
+    fn test_torrent_accessors() {
+        let torrent: Torrent = serde_json::from_value(serde_json::json!({
+            "name": "Ubuntu",
+            "status": 4,
+            "eta": -1,
+            "percentDone": 0.5,
+            "labels": ["linux", "iso"],
+            "magnetLink": "magnet:?xt=urn:btih:abc",
+            "hashString": 5,
+        }))
+        .unwrap();
+
+        assert_eq!(torrent.name(), Some("Ubuntu"));
+        assert_eq!(torrent.status(), Some(TorrentStatus::Downloading));
+        assert_eq!(torrent.eta(), Some(-1));
+        assert_eq!(torrent.percent_done(), Some(0.5));
+        assert_eq!(torrent.labels(), Some(vec!["linux", "iso"]));
+        assert_eq!(torrent.magnet_link(), Some("magnet:?xt=urn:btih:abc"));
+
+        // missing fields and fields with the wrong type aren't available
+        assert_eq!(torrent.id(), None);
+        assert_eq!(torrent.hash(), None);
+        assert_eq!(torrent.get(&TorrentGetKey::HashString), Some(&5.into()));
+
+        // the raw fields serialize back unchanged
+        assert_eq!(
+            serde_json::to_value(&torrent).unwrap()["labels"],
+            serde_json::json!(["linux", "iso"]),
+        );
+    }
+}
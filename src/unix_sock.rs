@@ -2,21 +2,114 @@ use anyhow::Context;
 use axum::http::Request;
 use axum::Router;
 use hyper::body::Incoming;
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use hyper_util::server::conn::auto::Builder;
-use tokio::net::UnixListener;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
 use tower::Service;
 
 use std::ffi::CString;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
+use std::num::NonZeroUsize;
 use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+/// Clients must finish sending a request's headers within this long of starting to send them.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limits the number of connections to the unix socket that are handled at the same time. There's
+/// usually no proxy in front of a unix socket to limit connections, so otherwise a flood of
+/// connections would spawn an unbounded number of tasks.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    /// The number of accepted connections that are still open. This doesn't include a permit that
+    /// was acquired for a connection that hasn't been accepted yet.
+    open: Arc<AtomicUsize>,
+    /// Whether all permits were in use the last time we tried to get one, so that the limit is
+    /// only logged once each time it's reached.
+    saturated: AtomicBool,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: NonZeroUsize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max.get())),
+            max: max.get(),
+            open: Arc::new(AtomicUsize::new(0)),
+            saturated: AtomicBool::new(false),
+        }
+    }
+
+    /// Wait until another connection can be handled. The permit should be given to `open` once
+    /// the connection is accepted.
+    async fn acquire(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        if let Ok(permit) = Arc::clone(&self.semaphore).try_acquire_owned() {
+            self.saturated.store(false, Ordering::Relaxed);
+            return Ok(permit);
+        }
+
+        if !self.saturated.swap(true, Ordering::Relaxed) {
+            println!(
+                "Reached the limit of {} unix socket connections, waiting for connections to close",
+                self.max,
+            );
+        }
+
+        Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .context("The connection limiter was closed")
+    }
+
+    /// Count an accepted connection as open until the returned guard is dropped.
+    fn open(&self, permit: OwnedSemaphorePermit) -> ConnectionGuard {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            _permit: permit,
+            open: Arc::clone(&self.open),
+        }
+    }
+
+    /// The number of connections that are currently open.
+    pub fn open_connections(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+}
+
+/// An open connection, which is counted by the `ConnectionLimiter` until it's dropped.
+struct ConnectionGuard {
+    _permit: OwnedSemaphorePermit,
+    open: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
 
 /// Serve `app` at a unix socket bound to `bind_addr` with `perms` permissions. Any existing unix
-/// socket at the given path will be removed.
-pub async fn serve<P: AsRef<Path>>(bind_addr: P, perms: u32, app: Router) -> anyhow::Result<()> {
+/// socket at the given path will be removed. At most `limiter`'s maximum number of connections
+/// are handled at the same time, and connections are closed after `idle_timeout` without any
+/// activity so that idle connections don't hold on to their permit.
+pub async fn serve<P: AsRef<Path>>(
+    bind_addr: P,
+    perms: u32,
+    limiter: Arc<ConnectionLimiter>,
+    idle_timeout: Duration,
+    app: Router,
+) -> anyhow::Result<()> {
     let bind_addr = bind_addr.as_ref();
 
     // delete any existing unix socket
@@ -56,26 +149,136 @@ pub async fn serve<P: AsRef<Path>>(bind_addr: P, perms: u32, app: Router) -> any
     // adapted from the example at
     // https://github.com/tokio-rs/axum/blob/e3bb7083c886247f4e6931e149ef6067e6b82e1b/examples/unix-domain-socket/src/main.rs
     loop {
+        // wait before accepting if there are too many connections, so that new connections are
+        // held in the listen backlog
+        let permit = limiter.acquire().await?;
+
         let (socket, _remote_addr) = listener.accept().await.context("Failed to accept socket")?;
+        let guard = limiter.open(permit);
 
         let tower_service = unwrap_infallible(make_service.call(&socket).await);
 
         tokio::spawn(async move {
-            let socket = TokioIo::new(socket);
+            // the connection is counted until it's closed
+            let _guard = guard;
+            serve_connection(socket, tower_service, idle_timeout).await;
+        });
+    }
+}
+
+/// Serve HTTP requests on the connection until it's closed, or until it has been idle for
+/// `idle_timeout`.
+async fn serve_connection(socket: UnixStream, service: Router, idle_timeout: Duration) {
+    let last_active = Arc::new(Mutex::new(Instant::now()));
+    let socket = TokioIo::new(ActivityStream {
+        inner: socket,
+        last_active: Arc::clone(&last_active),
+    });
 
-            let hyper_service = hyper::service::service_fn(move |request: Request<Incoming>| {
-                tower_service.clone().call(request)
-            });
+    let hyper_service =
+        hyper::service::service_fn(move |request: Request<Incoming>| service.clone().call(request));
 
-            let builder = Builder::new(TokioExecutor::new());
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(HEADER_READ_TIMEOUT);
 
-            if let Err(_err) = builder
-                .serve_connection_with_upgrades(socket, hyper_service)
-                .await
-            {
+    let conn = builder.serve_connection_with_upgrades(socket, hyper_service);
+    tokio::pin!(conn);
+
+    let mut closing = false;
+
+    loop {
+        let deadline = *last_active.lock().unwrap() + idle_timeout;
+
+        tokio::select! {
+            _rv = conn.as_mut() => {
                 // this can error for long-lived sse connections
+                return;
             }
-        });
+            _ = tokio::time::sleep_until(deadline) => {
+                if last_active.lock().unwrap().elapsed() < idle_timeout {
+                    continue;
+                }
+
+                if closing {
+                    // the client didn't finish reading a response, or a handler is stuck
+                    return;
+                }
+
+                // let any in-progress request finish before closing the connection, and close it
+                // anyways if it's still idle after another timeout
+                conn.as_mut().graceful_shutdown();
+                closing = true;
+                *last_active.lock().unwrap() = Instant::now();
+            }
+        }
+    }
+}
+
+/// A stream that records when data was last read from or written to it. Open SSE streams send
+/// periodic heartbeats, so they aren't considered idle.
+struct ActivityStream {
+    inner: UnixStream,
+    last_active: Arc<Mutex<Instant>>,
+}
+
+impl ActivityStream {
+    fn record<T>(&self, rv: Poll<std::io::Result<T>>) -> Poll<std::io::Result<T>> {
+        if let Poll::Ready(Ok(_)) = rv {
+            *self.last_active.lock().unwrap() = Instant::now();
+        }
+        rv
+    }
+}
+
+impl AsyncRead for ActivityStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let rv = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.record(rv)
+    }
+}
+
+impl AsyncWrite for ActivityStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let rv = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.record(rv)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let rv = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.record(rv)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
 
@@ -112,7 +315,7 @@ fn bind<S: AsRawFd, P: AsRef<Path>>(sock: S, path: P) -> std::io::Result<()> {
     let sun_path = &mut addr.sun_path[..sun_path_len - 1];
 
     if path.len() > sun_path.len() {
-        return Err(Error::new(ErrorKind::Other, "Path too long"));
+        return Err(Error::other("Path too long"));
     }
 
     sun_path[..path.len()].copy_from_slice(path);
@@ -186,6 +389,63 @@ fn unwrap_infallible<T>(result: Result<T, std::convert::Infallible>) -> T {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_connection_limiter() {
+        let limiter = ConnectionLimiter::new(NonZeroUsize::new(2).unwrap());
+        assert_eq!(limiter.open_connections(), 0);
+
+        let first = limiter.open(limiter.acquire().await.unwrap());
+        assert_eq!(limiter.open_connections(), 1);
+
+        // a permit for a connection that hasn't been accepted yet isn't counted
+        let second = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.open_connections(), 1);
+        let _second = limiter.open(second);
+        assert_eq!(limiter.open_connections(), 2);
+
+        // a third connection waits for one of the others to close
+        let wait = std::time::Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, limiter.acquire()).await.is_err());
+        assert!(limiter.saturated.load(Ordering::Relaxed));
+
+        drop(first);
+        assert_eq!(limiter.open_connections(), 1);
+        let third = tokio::time::timeout(wait, limiter.acquire()).await.unwrap();
+        let _third = limiter.open(third.unwrap());
+        assert_eq!(limiter.open_connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let app = Router::new().route("/", axum::routing::get(|| async { "hello" }));
+        let idle_timeout = Duration::from_millis(200);
+
+        // an idle connection is closed
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let serve = tokio::spawn(serve_connection(server, app.clone(), idle_timeout));
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), 0);
+        serve.await.unwrap();
+
+        // a keep-alive connection is closed once it's idle after a request
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let serve = tokio::spawn(serve_connection(server, app, idle_timeout));
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut buf));
+        read.await.unwrap().unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{resp}");
+        assert!(resp.ends_with("hello"), "{resp}");
+        serve.await.unwrap();
+    }
+
     #[test]
     fn test_ptr_and_len() {
         let x = [0u8; 10];
@@ -0,0 +1,16 @@
+// Copy the full value of a truncated field (for example a torrent's info hash) when its copy
+// button is clicked. The button must have the full value in its "data-full" attribute.
+document.addEventListener("click", function (e) {
+  const button = e.target.closest("button.copy-full");
+  if (button === null) {
+    return;
+  }
+
+  // the button may be inside a torrent's label, which we don't want to select
+  e.preventDefault();
+  e.stopPropagation();
+
+  navigator.clipboard.writeText(button.dataset.full).then(function () {
+    button.title = "Copied";
+  });
+});